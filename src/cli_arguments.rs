@@ -1,556 +1,518 @@
 use std::{
     fs::File,
     io::{BufRead, BufReader},
-    path::PathBuf,
     rc::Rc,
 };
 
-use clap::ValueEnum;
 use nu_plugin::{EngineInterface, EvaluatedCall};
-use nu_protocol::{
-    LabeledError, Record, ShellError, Signature, Spanned, SyntaxShape, engine::Closure,
-};
+use nu_protocol::{IntoSpanned, LabeledError, Signature, Span, Spanned, engine::Closure};
+use ratatui::crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 use shlex::Shlex;
 use skim::{
-    CaseMatching, FuzzyAlgorithm, RankCriteria, Selector, SkimOptions,
-    binds::KeyMap,
-    prelude::DefaultSkimSelector,
-    tui::options::{PreviewLayout, TuiLayout},
+    CaseMatching, FuzzyAlgorithm, RankCriteria, Selector, SkimOptions, binds::KeyMap,
+    prelude::DefaultSkimSelector, tui::event::Action, tui::options::TuiLayout,
 };
 
+use crate::closure_binding::TerminalSetup;
+use crate::history::History;
+use crate::option_kinds::{
+    Bind, Choice, ChoiceList, Color, Count, Delimiter, FieldRanges, FilePath, Header, Height,
+    LineCount, Margin, NOT_IN_ENV, NotInEnv, OptionKind, Predicate, PreviewWindow, Switch, Text,
+    TextList, value_enum_possibilities_string,
+};
+use crate::option_specs::{self, OptionSpec};
 use crate::predicate_based_selector::{CombinedSelector, PredicateBasedSelector};
+use crate::skim_colors;
+
+/// Same as the regular `sk`.
+const DEFAULT_HISTORY_SIZE: usize = 1000;
+
+/// Declares the options that are passed on to skim. Each option is declared once, with:
+///
+/// * The field that holds its value, and its kind (see `option_kinds`) - which decides the shape of
+///   the flag and how its value is parsed, both from the call and from `SKIM_DEFAULT_OPTIONS`.
+/// * The long flag, and optionally the short one.
+/// * The description of the flag.
+/// * How the value (`None` if the option was not given) is set in the `SkimOptions`. The options
+///   are applied in the order they are declared in.
+macro_rules! sk_options {
+    ($(
+        $field:ident: $kind:ty = $long:literal $(| $short:literal)?,
+            $description:expr,
+            $apply:expr;
+    )*) => {
+        /// The flags of the options, for parsing `SKIM_DEFAULT_OPTIONS`.
+        pub const SKIM_OPTION_SPECS: &[OptionSpec] = &[$(
+            OptionSpec {
+                long: $long,
+                short: short_flag(&[$($short)?]),
+                takes_value: <$kind as OptionKind>::TAKES_VALUE,
+            },
+        )*];
+
+        /// The options that were given - in the call, or in `SKIM_DEFAULT_OPTIONS`.
+        #[derive(Default)]
+        struct SkOptions {
+            $($field: Option<<$kind as OptionKind>::Value>,)*
+        }
+
+        impl SkOptions {
+            fn add_to_signature(mut signature: Signature) -> Signature {
+                $(
+                    signature = if <$kind as OptionKind>::TAKES_VALUE {
+                        signature.named(
+                            $long,
+                            <$kind as OptionKind>::shape(),
+                            $description,
+                            short_flag(&[$($short)?]),
+                        )
+                    } else {
+                        signature.switch($long, $description, short_flag(&[$($short)?]))
+                    };
+                )*
+                signature
+            }
+
+            #[allow(clippy::result_large_err)]
+            fn from_call(
+                call: &EvaluatedCall,
+                engine: &EngineInterface,
+            ) -> Result<Self, LabeledError> {
+                Ok(Self {
+                    $($field: <$kind as OptionKind>::from_call(call, $long, engine)?,)*
+                })
+            }
+
+            /// Set an option from `SKIM_DEFAULT_OPTIONS`.
+            fn set(&mut self, long: &str, value: Option<&str>) -> Result<(), String> {
+                match long {
+                    $($long => self.$field = Some(<$kind as OptionKind>::from_env(value)?),)*
+                    _ => return Err(NOT_IN_ENV.to_owned()),
+                }
+                Ok(())
+            }
+
+            /// The options of `self`, and the `defaults` for the options it does not have.
+            fn or(self, defaults: Self) -> Self {
+                Self {
+                    $($field: self.$field.or(defaults.$field),)*
+                }
+            }
+
+            fn apply(&self, options: &mut SkimOptions) {
+                $({
+                    let apply: fn(&mut SkimOptions, Option<&<$kind as OptionKind>::Value>) = $apply;
+                    apply(options, self.$field.as_ref());
+                })*
+            }
+        }
+    };
+}
+
+const fn short_flag(short: &[char]) -> Option<char> {
+    match short {
+        [short] => Some(*short),
+        _ => None,
+    }
+}
+
+fn is_on(switch: Option<&bool>) -> bool {
+    switch.copied().unwrap_or(false)
+}
+
+sk_options! {
+    bind: Bind = "bind",
+        "Custom key bindings. A record where the keys arae keymaps and the values are actions, or closures to run on the current item",
+        |options, bind| options.bind = bind.map(|bind| bind.actions.clone()).unwrap_or_default();
+    multi: Switch = "multi" | 'm',
+        "Select multiple values",
+        |options, multi| {
+            options.multi = is_on(multi);
+            options.no_multi = !options.multi;
+        };
+    prompt: Text = "prompt",
+        "Input prompt",
+        |options, prompt| options.prompt = prompt.cloned().unwrap_or_default();
+    cmd_prompt: Text = "cmd-prompt",
+        "Command mode prompt",
+        |options, cmd_prompt| options.cmd_prompt = cmd_prompt.cloned().unwrap_or_default();
+    expect: TextList = "expect",
+        "List of keys that can be used to complete skim. When given, the result is an {action, selected} record",
+        // Skim deprecated `--expect` in favor of binding the keys to `accept(<key>)`, which is
+        // also what makes the key show up in `final_event`.
+        |options, expect| {
+            options.bind.extend(
+                expect
+                    .into_iter()
+                    .flatten()
+                    .map(|key| format!("{key}:accept({key})")),
+            )
+        };
+    tac: Switch = "tac",
+        "Reverse  the  order  of  the search result (normally used together with --no-sort)",
+        |options, tac| options.tac = is_on(tac);
+    no_sort: Switch = "no-sort",
+        "Do not sort the search result (normally used together with --tac)",
+        |options, no_sort| options.no_sort = is_on(no_sort);
+    tiebreak: ChoiceList<RankCriteria> = "tiebreak",
+        "List of sort criteria to apply  when  the  scores are tied.",
+        |options, tiebreak| options.tiebreak = tiebreak.cloned().unwrap_or_default();
+    exact: Switch = "exact" | 'e',
+        "Enable exact-match",
+        |options, exact| options.exact = is_on(exact);
+    interactive: Switch = "interactive" | 'i',
+        "Start skim in interactive(command) mode",
+        |options, interactive| options.interactive = is_on(interactive);
+    query: Text = "query" | 'q',
+        "Specify the initial query",
+        |options, query| options.query = query.cloned();
+    cmd_query: Text = "cmd-query",
+        "Specify the initial query for interactive mode",
+        |options, cmd_query| options.cmd_query = cmd_query.cloned();
+    regex: Switch = "regex",
+        "Search with regular expression instead of fuzzy match",
+        |options, regex| options.regex = is_on(regex);
+    // Both are applied to the string items by `NuItem`
+    delimiter: Delimiter = "delimiter" | 'd',
+        "Regex that separates the fields of string items, for --with-nth (default: AWK-style whitespace)",
+        |options, delimiter| {
+            if let Some(delimiter) = delimiter {
+                options.delimiter = delimiter.clone();
+            }
+        };
+    with_nth: FieldRanges = "with-nth",
+        "Display and match only these fields of string items (e.g. 2.. or 1,3), keeping the returned items whole",
+        |options, with_nth| options.with_nth = with_nth.cloned().unwrap_or_default();
+    // `replstr` is not supported, since previews and commands are closures rather than templates
+    color: Color = "color",
+        "Color configuration. A string in skim's syntax, or a record of colors like in `color_config` (default: derived from `color_config`)",
+        |options, color| options.color = color.cloned();
+    margin: Margin = "margin",
+        "Margins around the finder. A comma-separated string, an int for all the sides, or a record like {top: 1, left: 10%}",
+        |options, margin| options.margin = margin.cloned().unwrap_or_else(|| "0,0,0,0".to_owned());
+    no_height: Switch = "no-height",
+        "Disable height feature",
+        |options, no_height| options.no_height = is_on(no_height);
+    no_clear: Switch = "no-clear",
+        "Do not clear finder interface on exit",
+        |options, no_clear| options.no_clear = is_on(no_clear);
+    no_clear_start: Switch = "no-clear-start",
+        "Do not clear on start",
+        |options, no_clear_start| options.no_clear_start = is_on(no_clear_start);
+    height: Height = "height",
+        "Display sk window below the cursor with the given height (a number of lines or a percentage like 40%) instead of using the full screen",
+        |options, height| options.height = height.cloned().unwrap_or_else(|| "100%".to_owned());
+    min_height: Count = "min-height",
+        "Minimum height when --height is given in percent. Ignored when --height is not specified",
+        |options, min_height| {
+            options.min_height = min_height.map_or_else(|| "10".to_owned(), usize::to_string)
+        };
+    preview_window: PreviewWindow = "preview-window",
+        "Determines the layout of the preview window. A string like right:50%:wrap, or a record like {position: right, size: 50%, wrap: true, hidden: false}",
+        |options, preview_window| {
+            options.preview_window = preview_window.cloned().unwrap_or_default()
+        };
+    reverse: Switch = "reverse",
+        "A synonym for --layout=reverse",
+        |options, reverse| options.reverse = is_on(reverse);
+    tabstop: Count = "tabstop",
+        "Number of spaces for a tab character",
+        |options, tabstop| options.tabstop = tabstop.copied().unwrap_or(8);
+    no_hscroll: Switch = "no-hscroll",
+        "Disable horizontal scroll",
+        |options, no_hscroll| options.no_hscroll = is_on(no_hscroll);
+    no_mouse: Switch = "no-mouse",
+        "Disable mouse",
+        |options, no_mouse| options.no_mouse = is_on(no_mouse);
+    inline_info: Switch = "inline-info",
+        "Display the finder info after the prompt with the default prefix ' < '",
+        |options, inline_info| options.inline_info = is_on(inline_info);
+    header: Header = "header",
+        "Set a header displayed above the items. A string, or a closure that generates it",
+        |options, header| options.header = header.cloned();
+    header_lines: LineCount = "header-lines",
+        "The first N items of the input are treated as a sticky, non-selectable header",
+        |options, header_lines| options.header_lines = header_lines.map_or(0, |&lines| lines.into());
+    layout: Choice<TuiLayout> = "layout",
+        format!("Choose the layout: [{}]", value_enum_possibilities_string::<TuiLayout>()),
+        // `--reverse` (which is applied before) takes precedence
+        |options, layout| {
+            options.layout = if options.reverse {
+                TuiLayout::Reverse
+            } else {
+                layout.copied().unwrap_or_default()
+            }
+        };
+    algorithm: Choice<FuzzyAlgorithm> = "algo",
+        format!(
+            "Fuzzy matching algorithm: [{}] (default: skim_v2)",
+            value_enum_possibilities_string::<FuzzyAlgorithm>(),
+        ),
+        |options, algorithm| options.algorithm = algorithm.copied().unwrap_or_default();
+    case: Choice<CaseMatching> = "case",
+        format!(
+            "Case sensitivity: [{}] (default: smart)",
+            value_enum_possibilities_string::<CaseMatching>(),
+        ),
+        |options, case| options.case = case.copied().unwrap_or_default();
+    // The histories are loaded by `CliArguments::new`
+    history: FilePath = "history",
+        "Load the query history from the file, and add the query to it on completion. A `.nuon` file stores the history as a list of records",
+        |_, _| {};
+    history_size: Count = "history-size",
+        "Maximum number of query history entries to keep (default: 1000)",
+        |_, _| {};
+    cmd_history: FilePath = "cmd-history",
+        "Load the command query history from the file, and add the command query to it on completion. A `.nuon` file stores the history as a list of records",
+        |_, _| {};
+    cmd_history_size: Count = "cmd-history-size",
+        "Maximum number of command query history entries to keep (default: 1000)",
+        |_, _| {};
+    keep_right: Switch = "keep-right",
+        "Keep the right end of the line visible when it's too long",
+        |options, keep_right| options.keep_right = is_on(keep_right);
+    skip_to_pattern: Text = "skip-to-pattern",
+        "Line will start with the start of the matched pattern",
+        |options, skip_to_pattern| options.skip_to_pattern = skip_to_pattern.cloned();
+    filter: NotInEnv<Text> = "filter",
+        "Filter mode. Do not start the interactive finder - just return all the items that match the given query, ordered by their score",
+        // Like in skim's own `SkimOptions::build`, which is not used here
+        |options, filter| {
+            options.filter = filter.cloned();
+            if options.query.is_none() {
+                options.query = filter.cloned();
+            }
+        };
+    select1: Switch = "select-1" | '1',
+        "Automatically select the only match",
+        |options, select1| options.select_1 = is_on(select1);
+    exit0: Switch = "exit-0" | '0',
+        "Exit immediately when there's no match",
+        |options, exit0| options.exit_0 = is_on(exit0);
+    sync: Switch = "sync",
+        "Wait for all the options to be available before choosing",
+        |options, sync| options.sync = is_on(sync);
+    // The selector is built by `CliArguments::new`
+    pre_select_n: Count = "pre-select-n",
+        "Pre-select the first n items in multi-selection mode",
+        |_, _| {};
+    pre_select_pat: Text = "pre-select-pat",
+        "Pre-select the matched items in multi-selection mode",
+        |_, _| {};
+    pre_select_items: TextList = "pre-select-items",
+        "Pre-select the items in the given list",
+        |_, _| {};
+    pre_select_file: FilePath = "pre-select-file",
+        "Pre-select the items read from file",
+        |_, _| {};
+    pre_select: Predicate = "pre-select",
+        "Pre-select the items that match the predicate",
+        |_, _| {};
+    no_clear_if_empty: Switch = "no-clear-if-empty",
+        "Do not clear previous items if command returns empty result",
+        |options, no_clear_if_empty| options.no_clear_if_empty = is_on(no_clear_if_empty);
+}
 
 pub struct CliArguments {
-    bind: Vec<String>,
-    multi: bool,
-    prompt: Option<String>,
-    cmd_prompt: Option<String>,
-    tac: bool,
-    no_sort: bool,
-    tiebreak: Vec<RankCriteria>,
-    exact: bool,
-    //cmd: Option<Closure>,
-    interactive: bool,
-    query: Option<String>,
-    cmd_query: Option<String>,
-    regex: bool,
-    //delimiter: Option<String>,
-    //replstr: Option<String>,
-    color: Option<String>,
-    margin: Option<String>,
-    no_height: bool,
-    no_clear: bool,
-    no_clear_start: bool,
-    min_height: Option<String>,
-    height: Option<String>,
-    //preview: Option<String>,
-    preview_window: Option<String>,
-    reverse: bool, // note that this does not (just) get paseed to CliArguments as is - it's there to modify --layout
-    tabstop: Option<usize>,
-    no_hscroll: bool,
-    no_mouse: bool,
-    inline_info: bool,
-    //header: Option<String>,
-    //header_lines: usize,
-    layout: Option<TuiLayout>,
-    algorithm: FuzzyAlgorithm,
-    case: CaseMatching,
-    //engine_factory: Option<Rc<dyn MatchEngineFactory>>,
-    //query_history: &'a [String],
-    //cmd_history: &'a [String],
-    //cmd_collector: Rc<RefCell<dyn CommandCollector>>,
-    keep_right: bool,
-    skip_to_pattern: Option<String>,
-    select1: bool,
-    exit0: bool,
-    sync: bool,
+    options: SkOptions,
+    history: Option<History>,
+    cmd_history: Option<History>,
     selector: Option<Rc<dyn Selector>>,
-    no_clear_if_empty: bool,
 }
 
 impl CliArguments {
     #[allow(clippy::result_large_err)]
     pub fn new(call: &EvaluatedCall, engine: &EngineInterface) -> Result<Self, LabeledError> {
-        let env_defaults = EnvDefaults::from_env(engine);
+        let mut options =
+            SkOptions::from_call(call, engine)?.or(EnvDefaults::from_env(engine).options);
+        if options.color.is_none() {
+            options.color = engine
+                .get_config()
+                .ok()
+                .and_then(|config| skim_colors::theme_from_color_config(&config));
+        }
+        // Options from `SKIM_DEFAULT_OPTIONS` have no span of their own
+        let span_of = |long: &str| {
+            call.get_flag_value(long)
+                .map_or(call.head, |value| value.span())
+        };
+        let load_history = |path: &Option<String>, long: &str, size: Option<usize>| {
+            path.clone()
+                .map(|path| {
+                    History::load(
+                        engine,
+                        path.into_spanned(span_of(long)),
+                        size.unwrap_or(DEFAULT_HISTORY_SIZE),
+                    )
+                })
+                .transpose()
+        };
+        let history = load_history(&options.history, "history", options.history_size)?;
+        let cmd_history = load_history(
+            &options.cmd_history,
+            "cmd-history",
+            options.cmd_history_size,
+        )?;
+        let selector = pre_selector(&options, engine, span_of("pre-select-file"))?;
         Ok(Self {
-            bind: if let Some(bind) = call.get_flag::<Record>("bind")? {
-                bind.iter()
-                    .map(|(key, value)| {
-                        let value = value.coerce_string()?;
-                        Ok(format!("{key}:{value}"))
-                    })
-                    .collect::<Result<Vec<String>, LabeledError>>()?
-            } else {
-                env_defaults.bind.unwrap_or_default()
-            },
-            multi: call.has_flag("multi")? || env_defaults.multi.unwrap_or(false),
-            prompt: call.get_flag("prompt")?.or(env_defaults.prompt),
-            cmd_prompt: call.get_flag("cmd-prompt")?.or(env_defaults.cmd_prompt),
-            tac: call.has_flag("tac")? || env_defaults.tac.unwrap_or(false),
-            no_sort: call.has_flag("no-sort")? || env_defaults.no_sort.unwrap_or(false),
-            tiebreak: {
-                let from_call = call
-                    .get_flag::<Vec<Spanned<String>>>("tiebreak")?
-                    .unwrap_or_default()
-                    .into_iter()
-                    .map(|flag| {
-                        RankCriteria::from_str(&flag.item, false).map_err(|_| {
-                            let possible_values = RankCriteria::value_variants()
-                                .iter()
-                                .flat_map(|v| {
-                                    Some(format!("`{}`", v.to_possible_value()?.get_name()))
-                                })
-                                .collect::<Vec<_>>()
-                                .join("/");
-                            LabeledError::new(format!(
-                                "Invalid tiebreak - legal options are {possible_values}"
-                            ))
-                            .with_label("here", flag.span)
-                        })
-                    })
-                    .collect::<Result<Vec<_>, _>>()?;
-                if from_call.is_empty() {
-                    env_defaults.tiebreak.unwrap_or_default()
-                } else {
-                    from_call
-                }
-            },
-            exact: call.has_flag("exact")? || env_defaults.exact.unwrap_or(false),
-            interactive: call.has_flag("interactive")? || env_defaults.interactive.unwrap_or(false),
-            query: call.get_flag("query")?.or(env_defaults.query),
-            cmd_query: call.get_flag("cmd-query")?.or(env_defaults.cmd_query),
-            regex: call.has_flag("regex")? || env_defaults.regex.unwrap_or(false),
-            color: call.get_flag("color")?.or(env_defaults.color),
-            margin: call.get_flag("margin")?.or(env_defaults.margin),
-            no_height: call.has_flag("no-height")? || env_defaults.no_height.unwrap_or(false),
-            no_clear: call.has_flag("no-clear")? || env_defaults.no_clear.unwrap_or(false),
-            no_clear_start: call.has_flag("no-clear-start")?
-                || env_defaults.no_clear_start.unwrap_or(false),
-            min_height: call
-                .get_flag::<i64>("min-height")?
-                .map(|num| num.to_string())
-                .or(env_defaults.min_height),
-            height: call.get_flag("height")?.or(env_defaults.height),
-            preview_window: call
-                .get_flag("preview-window")?
-                .or(env_defaults.preview_window),
-            reverse: call.has_flag("reverse")? || env_defaults.reverse.unwrap_or(false),
-            tabstop: call.get_flag::<usize>("tabstop")?.or(env_defaults.tabstop),
-            no_hscroll: call.has_flag("no-hscroll")? || env_defaults.no_hscroll.unwrap_or(false),
-            no_mouse: call.has_flag("no-mouse")? || env_defaults.no_mouse.unwrap_or(false),
-            inline_info: call.has_flag("inline-info")? || env_defaults.inline_info.unwrap_or(false),
-            layout: call
-                .get_flag_value("layout")
-                .map(parse_value_enum_from_flag)
-                .transpose()?
-                .or(env_defaults.layout),
-            algorithm: call
-                .get_flag_value("algo")
-                .map(parse_value_enum_from_flag)
-                .transpose()?
-                .unwrap_or(env_defaults.algorithm.unwrap_or_default()),
-            case: call
-                .get_flag_value("case")
-                .map(parse_value_enum_from_flag)
-                .transpose()?
-                .unwrap_or(env_defaults.case.unwrap_or_default()),
-            keep_right: call.has_flag("keep-right")? || env_defaults.keep_right.unwrap_or(false),
-            skip_to_pattern: call
-                .get_flag("skip-to-pattern")?
-                .or(env_defaults.skip_to_pattern),
-            select1: call.has_flag("select-1")? || env_defaults.select1.unwrap_or(false),
-            exit0: call.has_flag("exit-0")? || env_defaults.exit0.unwrap_or(false),
-            sync: call.has_flag("sync")? || env_defaults.sync.unwrap_or(false),
-            selector: {
-                let mut dumb_selector: Option<DefaultSkimSelector> = None;
-
-                // First apply env-derived pre-select options (if any),
-                // then apply flag-derived options additively.
-                if let Some(n) = env_defaults.pre_select_n {
-                    dumb_selector = Some(dumb_selector.take().unwrap_or_default().first_n(n));
-                }
-                if let Some(pat) = env_defaults.pre_select_pat {
-                    dumb_selector = Some(dumb_selector.take().unwrap_or_default().regex(&pat));
-                }
-                if let Some(items) = env_defaults.pre_select_items {
-                    dumb_selector = Some(dumb_selector.take().unwrap_or_default().preset(items));
-                }
-                if let Some(file_path) = env_defaults.pre_select_file {
-                    let file =
-                        File::open(file_path).map_err(|e| LabeledError::new(e.to_string()))?;
-                    let items = BufReader::new(file)
-                        .lines()
-                        .collect::<Result<Vec<String>, _>>()
-                        .map_err(|e| LabeledError::new(e.to_string()))?;
-                    dumb_selector = Some(dumb_selector.take().unwrap_or_default().preset(items));
-                }
-
-                // Now apply flag-derived pre-select options additively
-                if let Some(n) = call.get_flag::<usize>("pre-select-n")? {
-                    dumb_selector = Some(dumb_selector.take().unwrap_or_default().first_n(n));
-                }
-                if let Some(pat) = call.get_flag::<String>("pre-select-pat")? {
-                    dumb_selector = Some(dumb_selector.take().unwrap_or_default().regex(&pat));
-                }
-                if let Some(items) = call.get_flag::<Vec<String>>("pre-select-items")? {
-                    dumb_selector = Some(dumb_selector.take().unwrap_or_default().preset(items));
-                }
-                if let Some(file_path) = call.get_flag::<Spanned<PathBuf>>("pre-select-file")? {
-                    let file = File::open(file_path.item).map_err(|e| {
-                        LabeledError::new(e.to_string()).with_label("here", file_path.span)
-                    })?;
-                    let items = BufReader::new(file)
-                        .lines()
-                        .collect::<Result<Vec<String>, _>>()
-                        .map_err(|e| LabeledError::new(e.to_string()))?;
-                    dumb_selector = Some(dumb_selector.take().unwrap_or_default().preset(items));
-                }
-                if let Some(predicate) = call.get_flag::<Spanned<Closure>>("pre-select")? {
-                    let predicate_based_selector = PredicateBasedSelector {
-                        engine: engine.clone(),
-                        predicate,
-                    };
-                    if let Some(dumb_selector) = dumb_selector {
-                        Some(Rc::new(CombinedSelector(
-                            dumb_selector,
-                            predicate_based_selector,
-                        )))
-                    } else {
-                        Some(Rc::new(predicate_based_selector))
-                    }
-                } else if let Some(dumb_selector) = dumb_selector {
-                    Some(Rc::new(dumb_selector))
-                } else {
-                    None
-                }
-            },
-            no_clear_if_empty: call.has_flag("no-clear-if-empty")?
-                || env_defaults.no_clear_if_empty.unwrap_or(false),
+            options,
+            history,
+            cmd_history,
+            selector,
         })
     }
 
     pub fn add_to_signature(signature: Signature) -> Signature {
-        signature
-            .named(
-                "bind",
-                SyntaxShape::Record(Default::default()),
-                "Custom key bindings. A record where the keys arae keymaps and the values are actions",
-                None,
-            )
-            .switch("multi", "Select multiple values", Some('m'))
-            .named("prompt", SyntaxShape::String, "Input prompt", None)
-            .named("cmd-prompt", SyntaxShape::String, "Command mode prompt", None)
-            .switch("tac", "Reverse  the  order  of  the search result (normally used together with --no-sort)", None)
-            .switch("no-sort", "Do not sort the search result (normally used together with --tac)", None)
-            .named(
-                "tiebreak",
-                SyntaxShape::List(Box::new(SyntaxShape::String)),
-                "List of sort criteria to apply  when  the  scores are tied.",
-                None,
-            )
-            .switch(
-                "exact",
-                "Enable exact-match",
-                Some('e'),
-            )
-            .switch("interactive", "Start skim in interactive(command) mode", Some('i'))
-            .named(
-                "query",
-                SyntaxShape::String,
-                "Specify the initial query",
-                Some('q'),
-            )
-            .named(
-                "cmd-query",
-                SyntaxShape::String,
-                "Specify the initial query for interactive mode",
-                None,
-            )
-            .switch(
-                "regex",
-                "Search with regular expression instead of fuzzy match",
-                None,
-            )
-            .named("color", SyntaxShape::String, "Color configuration", None)
-            .named("margin", SyntaxShape::String, "Comma-separated expression for margins around the finder.", None)
-            .switch(
-                "no-height",
-                "Disable height feature",
-                 None,
-            )
-            .switch(
-                "no-clear",
-                "Do not clear finder interface on exit",
-                 None,
-            )
-            .switch(
-                "no-clear-start",
-                "Do not clear on start",
-                None,
-            )
-            .named(
-                "height",
-                SyntaxShape::String,
-                "Display sk window below the cursor with the given height instead of using the full screen",
-                None,
-            )
-            .named(
-                "min-height",
-                SyntaxShape::Number,
-                "Minimum height when --height is given in percent. Ignored when --height is not specified",
-                None,
-            )
-            .named(
-                "preview-window",
-                SyntaxShape::String,
-                "Determines the layout of the preview window",
-                None,
-            )
-            .switch(
-                "reverse",
-                "A synonym for --layout=reverse",
-                None,
-            )
-            .named(
-                "tabstop",
-                SyntaxShape::Number,
-                "Number of spaces for a tab character",
-                None,
-            )
-            .switch(
-                "no-hscroll",
-                "Disable horizontal scroll",
-                None,
-            )
-            .switch(
-                "no-mouse",
-                "Disable mouse",
-                None,
-            )
-            .switch(
-                "inline-info",
-                "Display the finder info after the prompt with the default prefix ' < '",
-                None,
-            )
-            .named(
-                "layout",
-                SyntaxShape::String,
-                format!("Choose the layout: [{}]", value_enum_possibilities_string::<TuiLayout>()),
-                None,
-            )
-            .named(
-                "algo",
-                SyntaxShape::String,
-                format!("Fuzzy matching algorithm: [{}] (default: skim_v2)", value_enum_possibilities_string::<FuzzyAlgorithm>()),
-                None,
-            )
-            .named(
-                "case",
-                SyntaxShape::String,
-                format!("Case sensitivity: [{}] (default: smart)", value_enum_possibilities_string::<CaseMatching>()),
-                None,
-            )
-            .switch(
-                "keep-right",
-                "Keep the right end of the line visible when it's too long",
-                None,
-            )
-            .named(
-                "skip-to-pattern",
-                SyntaxShape::String,
-                "Line will start with the start of the matched pattern",
-                None,
-            )
-            .switch(
-                "select-1",
-                "Automatically select the only match",
-                Some('1'),
-            )
-            .switch(
-                "exit-0",
-                "Exit immediately when there's no match",
-                Some('0'),
-            )
-            .switch(
-                "sync",
-                "Wait for all the options to be available before choosing",
-                None,
-            )
-            .named(
-                "pre-select-n",
-                SyntaxShape::Number,
-                "Pre-select the first n items in multi-selection mode",
-                None,
-            )
-            .named(
-                "pre-select-pat",
-                SyntaxShape::String,
-                "Pre-select the matched items in multi-selection mode",
-                None,
-            )
-            .named(
-                "pre-select-items",
-                SyntaxShape::List(Box::new(SyntaxShape::String)),
-                "Pre-select the items in the given list",
-                None,
-            )
-            .named(
-                "pre-select-file",
-                SyntaxShape::Filepath,
-                "Pre-select the items read from file",
-                None,
-            )
-            .named(
-                "pre-select",
-                SyntaxShape::Closure(Some(vec![])),
-                "Pre-select the items that match the predicate",
-                None,
-            )
-            .switch(
-                "no-clear-if-empty",
-                "Do not clear previous items if command returns empty result",
-                None,
-            )
+        SkOptions::add_to_signature(signature)
     }
 
-    pub fn to_skim_options(&self) -> SkimOptions {
-        let Self {
-            bind,
-            multi,
-            prompt,
-            cmd_prompt,
-            tac,
-            no_sort: nosort,
-            tiebreak,
-            exact,
-            interactive,
-            query,
-            cmd_query,
-            regex,
-            color,
-            margin,
-            no_height,
-            no_clear,
-            no_clear_start,
-            min_height,
-            height,
-            preview_window,
-            reverse,
-            tabstop,
-            no_hscroll,
-            no_mouse,
-            inline_info,
-            layout,
-            algorithm,
-            case,
-            keep_right,
-            skip_to_pattern,
-            select1: select_1,
-            exit0: exit_0,
-            sync,
-            selector,
-            no_clear_if_empty,
-        } = self;
+    /// The closures of the `{reload: ...}` bindings, in the order `to_skim_options` binds them.
+    pub fn reload_closures(&self) -> Vec<Spanned<Closure>> {
+        self.options
+            .bind
+            .iter()
+            .flat_map(|bind| &bind.reloads)
+            .map(|binding| binding.closure.clone())
+            .collect()
+    }
+
+    /// Whether keys are bound to accept the selection, so the result says which one was used.
+    pub fn has_expect(&self) -> bool {
+        self.options
+            .expect
+            .as_ref()
+            .is_some_and(|keys| !keys.is_empty())
+    }
+
+    /// Add the final queries to the `--history` and `--cmd-history` files.
+    #[allow(clippy::result_large_err)]
+    pub fn save_histories(
+        self,
+        engine: &EngineInterface,
+        query: &str,
+        cmd_query: Option<&str>,
+    ) -> Result<(), LabeledError> {
+        if let Some(history) = self.history {
+            history.save(engine, query)?;
+        }
+        if let Some(cmd_history) = self.cmd_history
+            && let Some(cmd_query) = cmd_query
+        {
+            cmd_history.save(engine, cmd_query)?;
+        }
+        Ok(())
+    }
 
+    pub fn to_skim_options(&self) -> SkimOptions {
         // I'd really prefer to use the construct syntax, but Rust won't let me (even with the
         // spread operator) because some fields are private.
         let mut result = SkimOptions::default();
-
-        result.bind = bind.clone();
+        self.options.apply(&mut result);
+        result.cmd = Some("ls".to_owned());
+        result.query_history = self.history.iter().flat_map(History::queries).collect();
+        result.cmd_history = self.cmd_history.iter().flat_map(History::queries).collect();
+        result.selector = self.selector.clone();
         result.keymap = {
             let mut keymap = KeyMap::default();
-            keymap.add_keymaps(bind.iter().map(String::as_str));
+            // Like the regular `sk`, a history file binds ctrl-p and ctrl-n to browse it - but
+            // here explicit bindings of these keys take precedence.
+            if self.history.is_some() || self.cmd_history.is_some() {
+                keymap.insert(
+                    KeyEvent::new(KeyCode::Char('p'), KeyModifiers::CONTROL),
+                    vec![Action::PreviousHistory],
+                );
+                keymap.insert(
+                    KeyEvent::new(KeyCode::Char('n'), KeyModifiers::CONTROL),
+                    vec![Action::NextHistory],
+                );
+            }
+            keymap.add_keymaps(result.bind.iter().map(String::as_str));
+            if let Some(bind) = &self.options.bind {
+                let terminal = TerminalSetup::from_options(&result);
+                for binding in &bind.closures {
+                    keymap.insert(binding.key, vec![binding.to_action(terminal)]);
+                }
+                for (index, binding) in bind.reloads.iter().enumerate() {
+                    keymap.insert(binding.key, vec![binding.to_action(index)]);
+                }
+            }
             keymap
         };
-        result.multi = *multi;
-        result.no_multi = !*multi;
-        result.prompt = prompt.as_deref().unwrap_or_default().to_owned();
-        result.cmd_prompt = cmd_prompt.as_deref().unwrap_or_default().to_owned();
-        result.tac = *tac;
-        result.no_sort = *nosort;
-        result.tiebreak = tiebreak.clone();
-        result.exact = *exact;
-        result.cmd = Some("ls".to_owned());
-        result.interactive = *interactive;
-        result.query = query.clone();
-        result.cmd_query = cmd_query.clone();
-        result.regex = *regex;
-        result.color = color.clone();
-        result.margin = margin.as_deref().unwrap_or("0,0,0,0").to_owned();
-        result.no_height = *no_height;
-        result.no_clear = *no_clear;
-        result.no_clear_start = *no_clear_start;
-        result.min_height = min_height.as_deref().unwrap_or("10").to_owned();
-        result.height = height.as_deref().unwrap_or("100%").to_owned();
-        result.preview_window = preview_window
-            .as_deref()
-            .map(PreviewLayout::from)
-            .unwrap_or_default();
-        result.reverse = *reverse;
-        result.tabstop = tabstop.unwrap_or(8);
-        result.no_hscroll = *no_hscroll;
-        result.no_mouse = *no_mouse;
-        result.inline_info = *inline_info;
-        result.layout = if *reverse {
-            TuiLayout::Reverse
-        } else {
-            layout.unwrap_or_default()
-        }
-        .to_owned();
-        result.algorithm = *algorithm;
-        result.case = *case;
-        result.keep_right = *keep_right;
-        result.skip_to_pattern = skip_to_pattern.clone();
-        result.select_1 = *select_1;
-        result.exit_0 = *exit_0;
-        result.sync = *sync;
-        result.selector = selector.clone();
-        result.no_clear_if_empty = *no_clear_if_empty;
         result
     }
 }
 
+/// The `--pre-select*` options, combined.
+#[allow(clippy::result_large_err)]
+fn pre_selector(
+    options: &SkOptions,
+    engine: &EngineInterface,
+    file_span: Span,
+) -> Result<Option<Rc<dyn Selector>>, LabeledError> {
+    let mut dumb_selector: Option<DefaultSkimSelector> = None;
+    if let Some(n) = options.pre_select_n {
+        dumb_selector = Some(dumb_selector.take().unwrap_or_default().first_n(n));
+    }
+    if let Some(pat) = &options.pre_select_pat {
+        dumb_selector = Some(dumb_selector.take().unwrap_or_default().regex(pat));
+    }
+    if let Some(items) = &options.pre_select_items {
+        dumb_selector = Some(
+            dumb_selector
+                .take()
+                .unwrap_or_default()
+                .preset(items.clone()),
+        );
+    }
+    if let Some(file_path) = &options.pre_select_file {
+        let file = File::open(file_path)
+            .map_err(|e| LabeledError::new(e.to_string()).with_label("here", file_span))?;
+        let items = BufReader::new(file)
+            .lines()
+            .collect::<Result<Vec<String>, _>>()
+            .map_err(|e| LabeledError::new(e.to_string()).with_label("here", file_span))?;
+        dumb_selector = Some(dumb_selector.take().unwrap_or_default().preset(items));
+    }
+    Ok(if let Some(predicate) = &options.pre_select {
+        let predicate_based_selector = PredicateBasedSelector {
+            engine: engine.clone(),
+            predicate: predicate.clone(),
+        };
+        if let Some(dumb_selector) = dumb_selector {
+            Some(Rc::new(CombinedSelector(
+                dumb_selector,
+                predicate_based_selector,
+            )))
+        } else {
+            Some(Rc::new(predicate_based_selector))
+        }
+    } else if let Some(dumb_selector) = dumb_selector {
+        Some(Rc::new(dumb_selector))
+    } else {
+        None
+    })
+}
+
 #[derive(Default)]
 struct EnvDefaults {
-    bind: Option<Vec<String>>,
-    multi: Option<bool>,
-    prompt: Option<String>,
-    cmd_prompt: Option<String>,
-    expect: Option<Vec<String>>,
-    tac: Option<bool>,
-    no_sort: Option<bool>,
-    tiebreak: Option<Vec<RankCriteria>>,
-    exact: Option<bool>,
-    interactive: Option<bool>,
-    query: Option<String>,
-    cmd_query: Option<String>,
-    regex: Option<bool>,
-    color: Option<String>,
-    margin: Option<String>,
-    no_height: Option<bool>,
-    no_clear: Option<bool>,
-    no_clear_start: Option<bool>,
-    min_height: Option<String>,
-    height: Option<String>,
-    preview_window: Option<String>,
-    reverse: Option<bool>,
-    tabstop: Option<usize>,
-    no_hscroll: Option<bool>,
-    no_mouse: Option<bool>,
-    inline_info: Option<bool>,
-    layout: Option<TuiLayout>,
-    algorithm: Option<FuzzyAlgorithm>,
-    case: Option<CaseMatching>,
-    keep_right: Option<bool>,
-    skip_to_pattern: Option<String>,
-    select1: Option<bool>,
-    exit0: Option<bool>,
-    sync: Option<bool>,
-    pre_select_n: Option<usize>,
-    pre_select_pat: Option<String>,
-    pre_select_items: Option<Vec<String>>,
-    pre_select_file: Option<PathBuf>,
-    no_clear_if_empty: Option<bool>,
+    options: SkOptions,
+    diagnostics: Vec<OptionDiagnostic>,
+}
+
+/// What was done with an option of `SKIM_DEFAULT_OPTIONS` or a key of `SKIM_NU_DEFAULTS` -
+/// reported by `sk defaults`.
+pub struct OptionDiagnostic {
+    pub option: String,
+    pub value: Option<String>,
+    /// Why the option was not used, or `None` if it was used.
+    pub problem: Option<String>,
+    /// Whether `sk` fails because of the problem, rather than ignoring the option.
+    pub rejected: bool,
+}
+
+/// Parse `SKIM_DEFAULT_OPTIONS`, and report what was done with each of its options.
+pub fn default_options_diagnostics(engine: &EngineInterface) -> Vec<OptionDiagnostic> {
+    EnvDefaults::from_env(engine).diagnostics
 }
 
 impl EnvDefaults {
@@ -564,258 +526,171 @@ impl EnvDefaults {
         }
     }
 
+    /// Invalid options are ignored, like in the regular `sk` - but unlike it, they are recorded in
+    /// the diagnostics.
     fn from_options_str(s: &str) -> Self {
         let mut out = EnvDefaults::default();
-        let mut it = Shlex::new(s)
-            .collect::<Vec<String>>()
-            .into_iter()
-            .peekable();
-
-        while let Some(tok) = it.next() {
-            if tok == "--" {
-                break;
-            }
-            if let Some(rest) = tok.strip_prefix("--") {
-                let (key, val_opt) = if let Some(eq_idx) = rest.find('=') {
-                    (
-                        rest[..eq_idx].to_string(),
-                        Some(rest[eq_idx + 1..].to_string()),
-                    )
-                } else {
-                    (rest.to_string(), None)
-                };
-                match key.as_str() {
-                    // boolean switches
-                    "multi" => out.multi = Some(true),
-                    "tac" => out.tac = Some(true),
-                    "no-sort" => out.no_sort = Some(true),
-                    "exact" => out.exact = Some(true),
-                    "interactive" => out.interactive = Some(true),
-                    "regex" => out.regex = Some(true),
-                    "no-height" => out.no_height = Some(true),
-                    "no-clear" => out.no_clear = Some(true),
-                    "no-clear-start" => out.no_clear_start = Some(true),
-                    "reverse" => out.reverse = Some(true),
-                    "no-hscroll" => out.no_hscroll = Some(true),
-                    "no-mouse" => out.no_mouse = Some(true),
-                    "inline-info" => out.inline_info = Some(true),
-                    "keep-right" => out.keep_right = Some(true),
-                    "select-1" => out.select1 = Some(true),
-                    "exit-0" => out.exit0 = Some(true),
-                    "sync" => out.sync = Some(true),
-                    "no-clear-if-empty" => out.no_clear_if_empty = Some(true),
-
-                    // string/numeric options
-                    "prompt" => {
-                        if let Some(v) = set_string(val_opt, &mut it) {
-                            out.prompt = Some(v);
-                        }
-                    }
-                    "cmd-prompt" => {
-                        if let Some(v) = set_string(val_opt, &mut it) {
-                            out.cmd_prompt = Some(v);
-                        }
-                    }
-                    "query" => {
-                        if let Some(v) = set_string(val_opt, &mut it) {
-                            out.query = Some(v);
-                        }
-                    }
-                    "cmd-query" => {
-                        if let Some(v) = set_string(val_opt, &mut it) {
-                            out.cmd_query = Some(v);
-                        }
-                    }
-                    "color" => {
-                        if let Some(v) = set_string(val_opt, &mut it) {
-                            out.color = Some(v);
-                        }
-                    }
-                    "margin" => {
-                        if let Some(v) = set_string(val_opt, &mut it) {
-                            out.margin = Some(v);
-                        }
-                    }
-                    "min-height" => {
-                        if let Some(v) = set_string(val_opt, &mut it) {
-                            out.min_height = Some(v);
-                        }
-                    }
-                    "height" => {
-                        if let Some(v) = set_string(val_opt, &mut it) {
-                            out.height = Some(v);
-                        }
-                    }
-                    "preview-window" => {
-                        if let Some(v) = set_string(val_opt, &mut it) {
-                            out.preview_window = Some(v);
-                        }
-                    }
-                    "layout" => {
-                        if let Some(v) = set_string(val_opt, &mut it) {
-                            out.layout = TuiLayout::from_str(&v, false).ok();
-                        }
-                    }
-                    "skip-to-pattern" => {
-                        if let Some(v) = set_string(val_opt, &mut it) {
-                            out.skip_to_pattern = Some(v);
-                        }
-                    }
-
-                    "tabstop" => {
-                        if let Some(v) = set_string(val_opt, &mut it)
-                            && let Ok(n) = v.parse::<usize>()
-                        {
-                            out.tabstop = Some(n);
-                        }
-                    }
-
-                    "algo" => {
-                        if let Some(v) = set_string(val_opt, &mut it) {
-                            out.algorithm = FuzzyAlgorithm::from_str(&v, false).ok();
-                        }
-                    }
-                    "case" => {
-                        if let Some(v) = set_string(val_opt, &mut it) {
-                            out.case = CaseMatching::from_str(&v, false).ok();
-                        }
-                    }
-
-                    "expect" => {
-                        if let Some(v) = set_string(val_opt, &mut it) {
-                            out.expect = Some(split_csv_like(&v))
-                        }
-                    }
-                    "tiebreak" => {
-                        if let Some(v) = set_string(val_opt, &mut it) {
-                            let vals = split_csv_like(&v);
-                            let mut parsed = Vec::new();
-                            for s in vals {
-                                if let Ok(rc) = RankCriteria::from_str(&s, false) {
-                                    parsed.push(rc);
-                                }
-                            }
-                            out.tiebreak = Some(parsed);
-                        }
-                    }
-                    "bind" => {
-                        if let Some(v) = set_string(val_opt, &mut it) {
-                            // accept comma-separated bind entries like skim
-                            let entries = v
-                                .split(',')
-                                .map(|s| s.trim().to_string())
-                                .filter(|s| !s.is_empty())
-                                .collect::<Vec<_>>();
-                            out.bind = Some(entries);
-                        }
-                    }
-
-                    "pre-select-n" => {
-                        if let Some(v) = set_string(val_opt, &mut it)
-                            && let Ok(n) = v.parse::<usize>()
-                        {
-                            out.pre_select_n = Some(n);
-                        }
-                    }
-                    "pre-select-pat" => {
-                        if let Some(v) = set_string(val_opt, &mut it) {
-                            out.pre_select_pat = Some(v);
-                        }
-                    }
-                    "pre-select-items" => {
-                        if let Some(v) = set_string(val_opt, &mut it) {
-                            out.pre_select_items = Some(split_csv_like(&v))
-                        }
-                    }
-                    "pre-select-file" => {
-                        if let Some(v) = set_string(val_opt, &mut it) {
-                            out.pre_select_file = Some(PathBuf::from(v));
-                        }
-                    }
-
-                    _ => { /* unrecognized; ignore */ }
-                }
-                continue;
-            }
-
-            if tok.starts_with('-') {
-                let flags = tok.trim_start_matches('-');
-                let mut chars = flags.chars().peekable();
-                while let Some(c) = chars.next() {
-                    match c {
-                        'm' => out.multi = Some(true),
-                        'e' => out.exact = Some(true),
-                        'i' => out.interactive = Some(true),
-                        '1' => out.select1 = Some(true),
-                        '0' => out.exit0 = Some(true),
-                        'q' => {
-                            // remainder of this token or next token
-                            let rest: String = chars.collect();
-                            if !rest.is_empty() {
-                                out.query = Some(rest);
-                            } else if let Some(v) = it.next() {
-                                out.query = Some(v);
-                            }
-                            break;
-                        }
-                        _ => {}
-                    }
+        for parsed in option_specs::parse_options(Shlex::new(s)) {
+            let result = match parsed.spec {
+                Ok(spec) if !spec.takes_value && parsed.value.is_some() => {
+                    Err("does not take a value".to_owned())
                 }
-            }
+                Ok(spec) => out.options.set(spec.long, parsed.value.as_deref()),
+                Err(problem) => Err(problem),
+            };
+            out.diagnose(parsed.option, parsed.value, result);
         }
-
         out
     }
-}
 
-fn set_string<I: Iterator<Item = String>>(
-    val_opt: Option<String>,
-    it: &mut std::iter::Peekable<I>,
-) -> Option<String> {
-    if let Some(v) = val_opt {
-        Some(v)
-    } else {
-        it.next()
+    fn diagnose(&mut self, option: String, value: Option<String>, result: Result<(), String>) {
+        self.diagnostics.push(OptionDiagnostic {
+            option,
+            value,
+            problem: result.err(),
+            rejected: false,
+        });
     }
 }
 
-fn split_csv_like(s: &str) -> Vec<String> {
-    s.split(&[',', ' '] as &[_])
-        .filter(|t| !t.is_empty())
-        .map(|t| t.to_string())
-        .collect()
-}
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use skim::SkimItem;
+
+    use super::*;
+
+    fn problems(options: &str) -> Vec<(String, Option<String>)> {
+        EnvDefaults::from_options_str(options)
+            .diagnostics
+            .into_iter()
+            .map(|diagnostic| (diagnostic.option, diagnostic.problem))
+            .collect()
+    }
 
-fn with_value_enum_possible_values<T: ValueEnum>(mut dlg: impl FnMut(&str)) {
-    for variant in T::value_variants() {
-        let Some(possible_value) = variant.to_possible_value() else {
-            continue;
+    #[test]
+    fn filter_mode_only_returns_the_matching_items() {
+        let arguments = CliArguments {
+            options: SkOptions {
+                filter: Some("ba".to_owned()),
+                ..Default::default()
+            },
+            history: None,
+            cmd_history: None,
+            selector: None,
         };
-        for value in possible_value.get_name_and_aliases() {
-            dlg(value);
-        }
+        let options = arguments.to_skim_options();
+        assert_eq!(options.query.as_deref(), Some("ba"));
+        let (tx, rx) = skim::prelude::unbounded();
+        tx.send(
+            ["foo", "bar", "baz"]
+                .map(|item| Arc::new(item.to_owned()) as Arc<dyn SkimItem>)
+                .to_vec(),
+        )
+        .unwrap();
+        // Skim waits for all the items in filter mode
+        drop(tx);
+        let output = skim::Skim::run_with(options, Some(rx)).unwrap();
+        let mut selected = output
+            .selected_items
+            .iter()
+            .map(|item| item.item.text().into_owned())
+            .collect::<Vec<_>>();
+        selected.sort();
+        assert_eq!(selected, ["bar", "baz"]);
     }
-}
 
-fn value_enum_possibilities_string<T: ValueEnum>() -> String {
-    let mut result = String::new();
-    with_value_enum_possible_values::<T>(|value| {
-        if !result.is_empty() {
-            result.push('|');
-        }
-        result.push_str(value);
-    });
-    result
-}
+    #[test]
+    fn only_expected_keys_wrap_the_result() {
+        let arguments = |expect: Option<Vec<String>>| CliArguments {
+            options: SkOptions {
+                expect,
+                ..Default::default()
+            },
+            history: None,
+            cmd_history: None,
+            selector: None,
+        };
+        assert!(!arguments(None).has_expect());
+        assert!(!arguments(Some(vec![])).has_expect());
+        assert!(arguments(Some(vec!["ctrl-v".to_owned()])).has_expect());
+    }
 
-fn parse_value_enum_from_flag<T: ValueEnum>(flag: nu_protocol::Value) -> Result<T, LabeledError> {
-    let str_value = flag.as_str()?;
-    T::from_str(str_value, false).map_err(|_| {
-        ShellError::InvalidValue {
-            valid: format!("[{}]", value_enum_possibilities_string::<T>()),
-            actual: str_value.to_owned(),
-            span: flag.span(),
+    #[test]
+    fn ignored_default_options_are_diagnosed() {
+        let diagnostics =
+            problems("-mx --algo=fast --height 40% --tabstop four --frobnicate stray");
+        let ignored = diagnostics
+            .iter()
+            .filter(|(_, problem)| problem.is_some())
+            .map(|(option, _)| option.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            ignored,
+            ["-x", "--algo", "--tabstop", "--frobnicate", "stray"]
+        );
+        assert_eq!(diagnostics.len(), 7);
+    }
+
+    #[test]
+    fn bundled_default_options_are_applied() {
+        let defaults = EnvDefaults::from_options_str("-eq'foo bar' -1m -p {} --tiebreak=index");
+        assert_eq!(defaults.options.exact, Some(true));
+        assert_eq!(defaults.options.query.as_deref(), Some("foo bar"));
+        assert_eq!(defaults.options.select1, Some(true));
+        assert_eq!(defaults.options.multi, Some(true));
+        assert_eq!(defaults.options.tiebreak, Some(vec![RankCriteria::Index]));
+        // Closures cannot be given in a string, but the value must still be skipped
+        let preview = &defaults.diagnostics[4];
+        assert_eq!(preview.option, "-p");
+        assert_eq!(preview.value.as_deref(), Some("{}"));
+        assert!(preview.problem.is_some());
+        assert_eq!(defaults.diagnostics.len(), 6);
+    }
+
+    #[test]
+    fn invalid_default_options_are_not_used() {
+        let defaults = EnvDefaults::from_options_str("--height tall --layout=reverse --case nope");
+        assert_eq!(defaults.options.height, None);
+        assert_eq!(defaults.options.layout, Some(TuiLayout::Reverse));
+        assert_eq!(defaults.options.case, None);
+
+        let defaults = EnvDefaults::from_options_str("--header-lines 70000");
+        assert_eq!(defaults.options.header_lines, None);
+        assert!(defaults.diagnostics[0].problem.is_some());
+
+        for fields in ["0", "2..0", "99999999999"] {
+            let defaults = EnvDefaults::from_options_str(&format!("--with-nth {fields}"));
+            assert_eq!(defaults.options.with_nth, None);
         }
-        .into()
-    })
+    }
+
+    #[test]
+    fn field_options_are_validated() {
+        let defaults = EnvDefaults::from_options_str(r"-d '\t|:' --with-nth 2..,-1");
+        let mut options = SkimOptions::default();
+        defaults.options.apply(&mut options);
+        assert_eq!(options.delimiter.as_str(), r"\t|:");
+        assert_eq!(options.with_nth, ["2..", "-1"]);
+
+        let defaults = EnvDefaults::from_options_str("--delimiter ( --with-nth 2,last");
+        assert!(defaults.options.delimiter.is_none());
+        assert_eq!(defaults.options.with_nth, None);
+    }
+
+    #[test]
+    fn options_are_applied_in_order() {
+        let defaults = EnvDefaults::from_options_str(
+            "--layout=reverse-list --reverse --bind ctrl-a:up --expect ctrl-v -m --tabstop 4",
+        );
+        let mut options = SkimOptions::default();
+        defaults.options.apply(&mut options);
+        assert_eq!(options.layout, TuiLayout::Reverse);
+        assert_eq!(options.bind, ["ctrl-a:up", "ctrl-v:accept(ctrl-v)"]);
+        assert!(options.multi);
+        assert!(!options.no_multi);
+        assert_eq!(options.tabstop, 4);
+        assert_eq!(options.min_height, "10");
+    }
 }
@@ -0,0 +1,276 @@
+use nu_plugin::EngineInterface;
+use nu_protocol::{
+    IntoSpanned, LabeledError, PipelineData, Record, Spanned, Value, engine::Closure,
+};
+use ratatui::crossterm::{self, event::KeyEvent};
+use skim::binds::parse_key;
+use skim::prelude::*;
+use skim::tui::event::ActionCallback;
+
+use crate::command_collector::reload_command;
+use crate::nu_item::NuItem;
+
+/// How Skim sets up the terminal, so that it can be set up the same way after a closure had it.
+#[derive(Clone, Copy)]
+pub struct TerminalSetup {
+    /// Only a full-height Skim uses the alternate screen.
+    pub alternate_screen: bool,
+    pub mouse_capture: bool,
+}
+
+impl TerminalSetup {
+    pub fn from_options(options: &SkimOptions) -> Self {
+        let full_height = options.no_height
+            || matches!(
+                skim::tui::Size::try_from(options.height.as_str()),
+                Ok(skim::tui::Size::Percent(100))
+            );
+        Self {
+            alternate_screen: full_height,
+            mouse_capture: !options.no_mouse,
+        }
+    }
+
+    fn leave(self) -> std::io::Result<()> {
+        if self.alternate_screen {
+            crossterm::execute!(std::io::stderr(), crossterm::terminal::LeaveAlternateScreen)?;
+        }
+        crossterm::execute!(std::io::stderr(), crossterm::event::DisableMouseCapture)
+    }
+
+    fn enter(self) -> std::io::Result<()> {
+        if self.alternate_screen {
+            crossterm::execute!(std::io::stderr(), crossterm::terminal::EnterAlternateScreen)?;
+        }
+        if self.mouse_capture {
+            crossterm::execute!(std::io::stderr(), crossterm::event::EnableMouseCapture)?;
+        }
+        Ok(())
+    }
+}
+
+/// Takes the terminal from Skim, and gives it back when dropped - even if something failed on the
+/// way, since Skim can't run on a terminal that is not in raw mode.
+struct TerminalHandover {
+    terminal: TerminalSetup,
+    raw_mode: bool,
+}
+
+impl TerminalHandover {
+    fn new(terminal: TerminalSetup) -> std::io::Result<Self> {
+        let handover = Self {
+            terminal,
+            raw_mode: crossterm::terminal::is_raw_mode_enabled()?,
+        };
+        if handover.raw_mode {
+            crossterm::terminal::disable_raw_mode()?;
+        }
+        terminal.leave()?;
+        Ok(handover)
+    }
+}
+
+impl Drop for TerminalHandover {
+    fn drop(&mut self) {
+        // Nothing else can be done if these fail
+        if self.raw_mode {
+            let _ = crossterm::terminal::enable_raw_mode();
+        }
+        let _ = self.terminal.enter();
+    }
+}
+
+/// A `--bind` entry whose action is a Nushell closure instead of a Skim action.
+pub struct ClosureBinding {
+    pub key: KeyEvent,
+    pub engine: EngineInterface,
+    pub closure: Spanned<Closure>,
+}
+
+impl ClosureBinding {
+    pub fn to_action(&self, terminal: TerminalSetup) -> Action {
+        let engine = self.engine.clone();
+        let closure = self.closure.clone();
+        Action::Custom(ActionCallback::new_sync(move |app| {
+            // Either the selected items or the one under the cursor
+            let values = app
+                .results()
+                .into_iter()
+                .filter_map(|item| {
+                    Some(
+                        (*item.item)
+                            .as_any()
+                            .downcast_ref::<NuItem>()?
+                            .value
+                            .clone(),
+                    )
+                })
+                .collect::<Vec<_>>();
+
+            // Like Skim's `execute`, give the terminal to the closure so that it can run
+            // interactive commands.
+            let _handover = TerminalHandover::new(terminal)?;
+            for value in values {
+                // Failures are ignored, like failed commands are ignored by Skim's `execute`
+                if let Ok(output) = engine.eval_closure_with_stream(
+                    &closure,
+                    vec![value.clone()],
+                    PipelineData::Value(value, None),
+                    false,
+                    false,
+                ) {
+                    let _ = output.drain();
+                }
+            }
+            Ok(vec![Event::Redraw])
+        }))
+    }
+}
+
+/// A `--bind` entry that replaces the items with the output of a closure.
+pub struct ReloadBinding {
+    pub key: KeyEvent,
+    pub closure: Spanned<Closure>,
+}
+
+impl ReloadBinding {
+    /// The `index` is the position of the closure in the `NuCommandCollector`'s `reload` list.
+    pub fn to_action(&self, index: usize) -> Action {
+        Action::Custom(ActionCallback::new_sync(move |_| {
+            Ok(vec![Event::Reload(reload_command(index))])
+        }))
+    }
+}
+
+/// The `--bind` record, split by the kind of the actions.
+#[derive(Default)]
+pub struct Bindings {
+    /// Regular skim bindings, like `ctrl-d:page-down`.
+    pub actions: Vec<String>,
+    pub closures: Vec<ClosureBinding>,
+    pub reloads: Vec<ReloadBinding>,
+}
+
+impl Bindings {
+    #[allow(clippy::result_large_err)]
+    pub fn from_record(record: Record, engine: &EngineInterface) -> Result<Self, LabeledError> {
+        let mut bindings = Self::default();
+        for (key, value) in record {
+            match Binding::parse(&key, value)? {
+                Binding::Action(action) => bindings.actions.push(action),
+                Binding::Closure(key, closure) => bindings.closures.push(ClosureBinding {
+                    key,
+                    engine: engine.clone(),
+                    closure,
+                }),
+                Binding::Reload(key, closure) => {
+                    bindings.reloads.push(ReloadBinding { key, closure })
+                }
+            }
+        }
+        Ok(bindings)
+    }
+
+    /// Comma-separated bindings, like in the regular `sk`.
+    pub fn from_options_str(s: &str) -> Self {
+        Self {
+            actions: s
+                .split(',')
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .collect(),
+            ..Self::default()
+        }
+    }
+}
+
+/// An entry of the `--bind` record.
+enum Binding {
+    /// A regular skim binding, like `ctrl-d:page-down`.
+    Action(String),
+    Closure(KeyEvent, Spanned<Closure>),
+    Reload(KeyEvent, Spanned<Closure>),
+}
+
+impl Binding {
+    #[allow(clippy::result_large_err)]
+    fn parse(key: &str, value: Value) -> Result<Self, LabeledError> {
+        let span = value.span();
+        let parse_key = || {
+            parse_key(key).map_err(|err| {
+                LabeledError::new(format!("Invalid key `{key}`")).with_label(err.to_string(), span)
+            })
+        };
+        match value {
+            Value::Closure { val, .. } => {
+                Ok(Self::Closure(parse_key()?, (*val).into_spanned(span)))
+            }
+            Value::Record { val, .. } => {
+                let Some((action, Value::Closure { val: closure, .. })) = val.iter().next() else {
+                    return Err(LabeledError::new("Invalid binding")
+                        .with_label("expected a record like {reload: {|| ...}}", span));
+                };
+                if action != "reload" || 1 < val.len() {
+                    return Err(LabeledError::new("Invalid binding")
+                        .with_label("only `reload` can be bound to a closure this way", span));
+                }
+                Ok(Self::Reload(
+                    parse_key()?,
+                    (**closure).clone().into_spanned(span),
+                ))
+            }
+            value => Ok(Self::Action(format!("{key}:{}", value.coerce_string()?))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nu_protocol::{BlockId, record};
+
+    use super::*;
+
+    fn closure() -> Value {
+        Value::test_closure(Closure {
+            block_id: BlockId::new(0),
+            captures: vec![],
+        })
+    }
+
+    #[test]
+    fn bindings_are_split_by_the_kind_of_action() {
+        assert!(matches!(
+            Binding::parse("ctrl-o", closure()),
+            Ok(Binding::Closure(key, _)) if key == parse_key("ctrl-o").unwrap()
+        ));
+        assert!(matches!(
+            Binding::parse("alt-r", Value::test_record(record! { "reload" => closure() })),
+            Ok(Binding::Reload(key, _)) if key == parse_key("alt-r").unwrap()
+        ));
+        assert!(matches!(
+            Binding::parse("ctrl-d", Value::test_string("page-down")),
+            Ok(Binding::Action(action)) if action == "ctrl-d:page-down"
+        ));
+    }
+
+    #[test]
+    fn invalid_bindings_are_rejected() {
+        for (key, value) in [
+            ("no-such-key", closure()),
+            (
+                "alt-r",
+                Value::test_record(record! { "execute" => closure() }),
+            ),
+            (
+                "alt-r",
+                Value::test_record(record! { "reload" => closure(), "extra" => closure() }),
+            ),
+            (
+                "alt-r",
+                Value::test_record(record! { "reload" => Value::test_string("ls") }),
+            ),
+        ] {
+            assert!(Binding::parse(key, value).is_err());
+        }
+    }
+}
@@ -1,15 +1,37 @@
+use std::collections::BTreeMap;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 
-use nu_protocol::{PipelineData, Span, Spanned, Value, engine::Closure};
+use nu_protocol::shell_error::generic::GenericError;
+use nu_protocol::{PipelineData, ShellError, Span, Spanned, Value, engine::Closure};
 use shlex::Shlex;
 use skim::reader::CommandCollector;
-use skim::{SkimItem, prelude::unbounded};
+use skim::{
+    SkimItem,
+    prelude::{Receiver, Sender, bounded, unbounded},
+};
 
-use crate::{command_context::CommandContext, nu_item::NuItem};
+use crate::command_context::CommandContext;
+use crate::line_parser::{LineParser, numbered_values};
+use crate::nu_item::NuItem;
 
 pub struct NuCommandCollector {
     pub context: Arc<CommandContext>,
-    pub closure: Spanned<Closure>,
+    /// The `--cmd` closure
+    pub closure: Option<Spanned<Closure>>,
+    /// Closures bound to keys for reloading the items
+    pub reload: Vec<Spanned<Closure>>,
+    /// `--parse`, which applies to the output of the closures too
+    pub parser: Option<Arc<LineParser>>,
+}
+
+const RELOAD_COMMAND_PREFIX: &str = "nu-reload:";
+
+/// What `ReloadBinding` sends to Skim as the command to reload with. Since queries are
+/// shell-quoted when Skim expands `{q}`, they can never be confused with it.
+pub fn reload_command(index: usize) -> String {
+    format!("{RELOAD_COMMAND_PREFIX}{index}")
 }
 
 impl CommandCollector for NuCommandCollector {
@@ -21,61 +43,62 @@ impl CommandCollector for NuCommandCollector {
         let (tx, rx) = unbounded::<Vec<Arc<dyn SkimItem>>>();
         let (tx_interrupt, rx_interrupt) = unbounded();
         let context = self.context.clone();
-        let closure = self.closure.clone();
-        let cmd = Shlex::new(cmd)
-            .next()
-            .expect("Skim's {q} should have produced a single shell-quoted value");
+        let parser = self.parser.clone();
+        // The items are about to be replaced, and the new ones would reuse the old indices
+        if let Some(preview_cache) = &context.preview_cache {
+            preview_cache.clear();
+        }
+        let invocation = if let Some(index) = cmd
+            .strip_prefix(RELOAD_COMMAND_PREFIX)
+            .and_then(|index| index.parse::<usize>().ok())
+        {
+            Ok((self.reload.get(index).cloned(), vec![]))
+        } else if let Some(query) = Shlex::new(cmd).next() {
+            Ok((
+                self.closure.clone(),
+                vec![Value::string(query, Span::unknown())],
+            ))
+        } else {
+            Err(ShellError::Generic(GenericError::new(
+                "Cannot parse the command query",
+                format!(
+                    "Skim's {{q}} should have produced a single shell-quoted value, got `{cmd}`"
+                ),
+                Span::unknown(),
+            )))
+        };
         std::thread::spawn(move || {
+            let (closure, positional) = match invocation {
+                Ok((Some(closure), positional)) => (closure, positional),
+                Ok((None, _)) => return,
+                Err(err) => {
+                    send_error(&context, &tx, err);
+                    return;
+                }
+            };
             components_to_stop.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
 
             match context.engine.eval_closure_with_stream(
                 &closure,
-                vec![Value::string(cmd, Span::unknown())],
+                positional,
                 PipelineData::Empty,
                 true,
                 true,
             ) {
-                Ok(PipelineData::ByteStream(stream, _)) => {
-                    let span = stream.span();
-                    if let Some(lines) = stream.lines() {
-                        for line in lines {
-                            if rx_interrupt.try_recv().is_ok() {
-                                break;
-                            }
-                            let send_result = match line {
-                                Ok(line) => tx.send(vec![Arc::new(NuItem::new(
-                                    context.clone(),
-                                    Value::string(line, span),
-                                ))]),
-                                Err(err) => tx.send(vec![Arc::new(NuItem::new(
-                                    context.clone(),
-                                    Value::error(err, span),
-                                ))]),
-                            };
-                            if send_result.is_err() {
-                                break;
-                            }
-                        }
+                Ok(data) => {
+                    if let Some(values) = pipeline_values(data) {
+                        let item_context = context.clone();
+                        send_values(
+                            numbered_values(values, parser),
+                            move |_, (index, value)| {
+                                NuItem::new(item_context.clone(), index, value)
+                            },
+                            &tx,
+                            Some(&rx_interrupt),
+                        );
                     }
                 }
-                Ok(stream) => {
-                    for value in stream {
-                        if rx_interrupt.try_recv().is_ok() {
-                            break;
-                        }
-                        let send_result =
-                            tx.send(vec![Arc::new(NuItem::new(context.clone(), value))]);
-                        if send_result.is_err() {
-                            break;
-                        }
-                    }
-                }
-                Err(err) => {
-                    let _ = tx.send(vec![Arc::new(NuItem::new(
-                        context.clone(),
-                        Value::error(err, Span::unknown()),
-                    ))]);
-                }
+                Err(err) => send_error(&context, &tx, err),
             }
 
             components_to_stop.fetch_sub(1, std::sync::atomic::Ordering::SeqCst);
@@ -83,3 +106,178 @@ impl CommandCollector for NuCommandCollector {
         (rx, tx_interrupt)
     }
 }
+
+/// Show the error as the only item, since there is no other way to report it from the collector.
+fn send_error(
+    context: &Arc<CommandContext>,
+    sender: &Sender<Vec<Arc<dyn SkimItem>>>,
+    err: ShellError,
+) {
+    let _ = sender.send(vec![Arc::new(NuItem::new(
+        context.clone(),
+        0,
+        Value::error(err, Span::unknown()),
+    ))]);
+}
+
+/// The values of the pipeline data, with byte streams split into lines. Returns `None` for byte
+/// streams that cannot be split into lines.
+pub fn pipeline_values(data: PipelineData) -> Option<Box<dyn Iterator<Item = Value> + Send>> {
+    match data {
+        PipelineData::ByteStream(stream, _) => {
+            let span = stream.span();
+            let lines = stream.lines()?;
+            Some(Box::new(lines.map(move |line| match line {
+                Ok(text) => Value::string(text, span),
+                Err(err) => Value::error(err, span),
+            })))
+        }
+        data => Some(Box::new(data.into_iter())),
+    }
+}
+
+/// The most items that are sent to Skim at once.
+const CHUNK_SIZE: usize = 1024;
+
+/// The most items that are being created or are waiting for the ones before them, so that neither
+/// a slow item nor a slow reader makes them pile up.
+const MAX_IN_FLIGHT: usize = 4 * CHUNK_SIZE;
+
+/// How often to check for an interrupt while waiting for the items.
+const INTERRUPT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Send the values to Skim as items, until they run out, Skim stops listening, or an interrupt is
+/// received. `make_item` gets the position of each value in the input along with the value.
+///
+/// Creating a `NuItem` may mean calling the `--format` closure, which is a round-trip to the
+/// engine - so the items are created by a pool of workers, and then sent to Skim in input order,
+/// in chunks of whatever is ready.
+///
+/// Returns as soon as it stops, even if the values are still being waited for - the threads that
+/// read and convert them stop on their own afterwards.
+pub fn send_values<T, I>(
+    values: impl Iterator<Item = T> + Send + 'static,
+    make_item: impl Fn(usize, T) -> I + Send + Sync + 'static,
+    sender: &Sender<Vec<Arc<dyn SkimItem>>>,
+    interrupt: Option<&Receiver<i32>>,
+) where
+    T: Send + 'static,
+    I: SkimItem,
+{
+    let workers = std::thread::available_parallelism().map_or(1, |workers| workers.get());
+    // A value is only read after it gets one of these, and it's returned when its item is sent
+    let (permit_tx, permit_rx) = bounded::<()>(MAX_IN_FLIGHT);
+    for _ in 0..MAX_IN_FLIGHT {
+        let _ = permit_tx.send(());
+    }
+    let (job_tx, job_rx) = bounded::<(usize, T)>(workers);
+    let (item_tx, item_rx) = bounded::<(usize, I)>(MAX_IN_FLIGHT);
+    let stopped = Arc::new(AtomicBool::new(false));
+
+    {
+        let stopped = stopped.clone();
+        std::thread::spawn(move || {
+            for job in values.enumerate() {
+                if stopped.load(Ordering::Relaxed)
+                    || permit_rx.recv().is_err()
+                    || job_tx.send(job).is_err()
+                {
+                    break;
+                }
+            }
+        });
+    }
+    let make_item = Arc::new(make_item);
+    for _ in 0..workers {
+        let job_rx = job_rx.clone();
+        let item_tx = item_tx.clone();
+        let make_item = make_item.clone();
+        std::thread::spawn(move || {
+            while let Ok((index, value)) = job_rx.recv() {
+                let item = make_item(index, value);
+                if item_tx.send((index, item)).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+    // Only the threads should hold these, so that the channels close when the threads are done
+    drop(job_rx);
+    drop(item_tx);
+
+    let mut pending = BTreeMap::new();
+    let mut next_index = 0;
+    loop {
+        // `Ok(None)` means nothing was sent yet - any other result means we should stop
+        if interrupt.is_some_and(|interrupt| !matches!(interrupt.try_recv(), Ok(None))) {
+            break;
+        }
+        match item_rx.recv_timeout(INTERRUPT_POLL_INTERVAL) {
+            Ok((index, item)) => {
+                pending.insert(index, item);
+            }
+            Err(_) if item_rx.is_terminated() => break,
+            Err(_) => continue,
+        }
+        while pending.len() < CHUNK_SIZE
+            && let Ok(Some((index, item))) = item_rx.try_recv()
+        {
+            pending.insert(index, item);
+        }
+        let mut chunk = Vec::<Arc<dyn SkimItem>>::new();
+        while let Some(item) = pending.remove(&next_index) {
+            chunk.push(Arc::new(item));
+            next_index += 1;
+            let _ = permit_tx.send(());
+        }
+        if !chunk.is_empty() && sender.send(chunk).is_err() {
+            // Assuming the receiver was closed because the user picked an item
+            break;
+        }
+    }
+    // Make the reader and the workers stop
+    stopped.store(true, Ordering::Relaxed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn items_are_sent_in_input_order() {
+        let (sender, receiver) = unbounded();
+        send_values(
+            0..3000,
+            |index, value: usize| {
+                assert_eq!(index, value);
+                // Some items take longer, so that the workers finish them out of order
+                if value.is_multiple_of(7) {
+                    std::thread::sleep(Duration::from_micros(200));
+                }
+                value.to_string()
+            },
+            &sender,
+            None,
+        );
+        drop(sender);
+        let mut texts = Vec::new();
+        while let Ok(chunk) = receiver.recv() {
+            assert!(chunk.len() <= CHUNK_SIZE);
+            texts.extend(chunk.iter().map(|item| item.text().into_owned()));
+        }
+        assert_eq!(
+            texts,
+            (0..3000).map(|value| value.to_string()).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn interrupts_stop_waiting_for_the_values() {
+        let (sender, _receiver) = unbounded();
+        let (interrupt_tx, interrupt_rx) = unbounded();
+        interrupt_tx.send(1).unwrap();
+        // The values never end, and never come
+        let values = std::iter::repeat_with(|| std::thread::sleep(Duration::from_secs(60)));
+        send_values(values, |_, ()| String::new(), &sender, Some(&interrupt_rx));
+    }
+}
@@ -6,12 +6,28 @@ use nu_protocol::{FromValue, IntoValue};
 use nu_protocol::{
     IntoSpanned, LabeledError, PipelineData, ShellError, Spanned, Value, engine::Closure,
 };
+use regex::Regex;
+use skim::field::FieldRange;
+
+use crate::format_cache::FormatCache;
+use crate::nu_item::{PreviewParams, RenderedPreview};
+use crate::preview_cache::PreviewCache;
+use crate::table_layout::TableLayout;
 
 pub struct CommandContext {
     pub engine: EngineInterface,
     pub nu_config: Arc<nu_protocol::Config>,
     pub format: MapperFlag,
+    format_cache: Option<FormatCache>,
     pub preview: MapperFlag,
+    /// Whether `--preview` receives the `PreviewParams`, which makes the previews depend on the
+    /// query and the selection too.
+    pub preview_takes_params: bool,
+    pub preview_cache: Option<PreviewCache<PreviewParams, RenderedPreview>>,
+    pub nth: Vec<CellPath>,
+    pub table: Option<TableLayout>,
+    /// The `--delimiter` and the `--with-nth` fields to display of string items.
+    pub with_nth: Option<(Regex, Vec<FieldRange>)>,
 }
 
 impl CommandContext {
@@ -21,15 +37,40 @@ impl CommandContext {
             engine: engine.clone(),
             nu_config: engine.get_config()?.clone(),
             format: MapperFlag::None,
+            format_cache: None,
             preview: MapperFlag::None,
+            preview_takes_params: false,
+            preview_cache: None,
+            nth: Vec::new(),
+            table: None,
+            with_nth: None,
         })
     }
+
+    /// Remember what the `--format` closure returns for each value - worth it when the items can
+    /// be created again, by `--cmd` or a reload.
+    pub fn cache_formats(&mut self) {
+        self.format_cache = Some(FormatCache::default());
+    }
+
+    /// What `--format` returns for the value. With `cache_formats`, closures are only called once
+    /// for each value.
+    pub fn format<'a>(&self, value: &'a Value) -> Cow<'a, Value> {
+        match (&self.format, &self.format_cache) {
+            (MapperFlag::Closure(_), Some(format_cache)) => Cow::Owned(
+                format_cache.get_or_format(value, || self.format.map(self, value).into_owned()),
+            ),
+            (format, _) => format.map(self, value),
+        }
+    }
 }
 
 pub enum MapperFlag {
     None,
     Closure(Spanned<Closure>),
     CellPath(Spanned<CellPath>),
+    /// The defaults of the picker commands
+    Builtin(fn(&CommandContext, &Value) -> Value),
 }
 
 impl FromValue for MapperFlag {
@@ -71,13 +112,31 @@ impl TryFrom<Value> for MapperFlag {
 }
 
 impl MapperFlag {
+    /// Whether the mapper receives the positional arguments of `map_with_args`. Only closures do -
+    /// and since a plugin can't see whether a closure declares parameters for them, every closure
+    /// is assumed to.
+    pub fn takes_params(&self) -> bool {
+        matches!(self, MapperFlag::Closure(_))
+    }
+
     pub fn map<'a>(&self, context: &CommandContext, value: &'a Value) -> Cow<'a, Value> {
+        self.map_with_args(context, value, vec![])
+    }
+
+    /// Like `map`, but also passes positional arguments to the closure. Closures that don't
+    /// declare parameters for them just ignore them.
+    pub fn map_with_args<'a>(
+        &self,
+        context: &CommandContext,
+        value: &'a Value,
+        positional: Vec<Value>,
+    ) -> Cow<'a, Value> {
         match self {
             MapperFlag::None => Cow::Borrowed(value),
             MapperFlag::Closure(closure) => Cow::Owned(
                 match context.engine.eval_closure_with_stream(
                     closure,
-                    vec![],
+                    positional,
                     PipelineData::Value(value.clone(), None),
                     true,
                     true,
@@ -100,6 +159,7 @@ impl MapperFlag {
                     Err(err) => Value::error(err, closure.span),
                 },
             ),
+            MapperFlag::Builtin(function) => Cow::Owned(function(context, value)),
             MapperFlag::CellPath(cell_path) => {
                 match value.follow_cell_path(&cell_path.item.members) {
                     Ok(cell_value) => cell_value,
@@ -0,0 +1,126 @@
+use std::collections::{HashMap, VecDeque};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::Mutex;
+
+use nu_protocol::Value;
+
+/// The most formatted values to keep.
+const CAPACITY: usize = 10_000;
+
+/// Remembers what the `--format` closure returned for each value, so that values that come again -
+/// in the output of `--cmd` as the query changes, or after a reload - are not sent to the engine
+/// again. It's only used when there is `--cmd` or a reload binding, since the values of a single
+/// input never come again.
+#[derive(Default)]
+pub struct FormatCache {
+    entries: Mutex<Entries>,
+}
+
+#[derive(Default)]
+struct Entries {
+    /// Values are looked up by their hash, and then compared - since they can't be used as keys.
+    by_hash: HashMap<u64, Vec<(Value, Value)>>,
+    /// The hashes in the order they were added, so that the oldest values are dropped first.
+    order: VecDeque<u64>,
+}
+
+impl FormatCache {
+    pub fn get_or_format(&self, value: &Value, format: impl FnOnce() -> Value) -> Value {
+        let mut hasher = DefaultHasher::new();
+        hash_value(value, &mut hasher);
+        let hash = hasher.finish();
+        if let Ok(entries) = self.entries.lock()
+            && let Some((_, formatted)) = entries
+                .by_hash
+                .get(&hash)
+                .and_then(|values| values.iter().find(|(cached, _)| cached == value))
+        {
+            return formatted.clone();
+        }
+        let formatted = format();
+        // Errors may not happen the next time
+        if formatted.is_error() {
+            return formatted;
+        }
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.insert(hash, value.clone(), formatted.clone());
+        }
+        formatted
+    }
+}
+
+impl Entries {
+    fn insert(&mut self, hash: u64, value: Value, formatted: Value) {
+        let values = self.by_hash.entry(hash).or_default();
+        if values.iter().any(|(cached, _)| *cached == value) {
+            // Another thread formatted it meanwhile
+            return;
+        }
+        values.push((value, formatted));
+        self.order.push_back(hash);
+        while CAPACITY < self.order.len()
+            && let Some(oldest) = self.order.pop_front()
+        {
+            if let Some(values) = self.by_hash.get_mut(&oldest) {
+                values.remove(0);
+                if values.is_empty() {
+                    self.by_hash.remove(&oldest);
+                }
+            }
+        }
+    }
+}
+
+/// Hash what `Value`'s `PartialEq` compares, without the spans. Values of the other types are
+/// only told apart by their type, and then by comparing them.
+fn hash_value(value: &Value, hasher: &mut impl Hasher) {
+    std::mem::discriminant(value).hash(hasher);
+    match value {
+        Value::Bool { val, .. } => val.hash(hasher),
+        Value::Int { val, .. } => val.hash(hasher),
+        Value::Float { val, .. } => val.to_bits().hash(hasher),
+        Value::String { val, .. } | Value::Glob { val, .. } => val.hash(hasher),
+        Value::Filesize { val, .. } => val.get().hash(hasher),
+        Value::Duration { val, .. } => val.hash(hasher),
+        Value::Date { val, .. } => val.hash(hasher),
+        Value::Binary { val, .. } => val.hash(hasher),
+        Value::Record { val, .. } => {
+            for (column, value) in val.iter() {
+                column.hash(hasher);
+                hash_value(value, hasher);
+            }
+        }
+        Value::List { vals, .. } => {
+            vals.len().hash(hasher);
+            for value in vals {
+                hash_value(value, hasher);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+
+    #[test]
+    fn values_are_formatted_once() {
+        let cache = FormatCache::default();
+        let config = nu_protocol::Config::default();
+        let calls = Cell::new(0);
+        let format = |value: &Value| {
+            cache.get_or_format(value, || {
+                calls.set(calls.get() + 1);
+                Value::test_string(format!("<{}>", value.to_expanded_string(", ", &config)))
+            })
+        };
+        assert_eq!(format(&Value::test_int(1)), Value::test_string("<1>"));
+        assert_eq!(format(&Value::test_int(1)), Value::test_string("<1>"));
+        // Same text, different value
+        assert_eq!(format(&Value::test_string("1")), Value::test_string("<1>"));
+        assert_eq!(calls.get(), 2);
+    }
+}
@@ -0,0 +1,188 @@
+use std::path::{Path, PathBuf};
+
+use nu_plugin::{EngineInterface, EvaluatedCall};
+use nu_protocol::{
+    IntoSpanned, LabeledError, PipelineData, ShellError, Span, Spanned, Value, record,
+};
+
+/// A `--history` or `--cmd-history` file.
+///
+/// Like in the regular `sk`, it is a plain text file with one query per line - unless its
+/// extension is `.nuon`, in which case it is a list of `{query, time}` records that can be
+/// inspected with `open`.
+pub struct History {
+    path: Spanned<PathBuf>,
+    size: usize,
+    /// Strings for plain text files, records for `.nuon` files.
+    entries: Vec<Value>,
+}
+
+impl History {
+    /// A missing file is treated as an empty history, since it'll be created on save.
+    #[allow(clippy::result_large_err)]
+    pub fn load(
+        engine: &EngineInterface,
+        path: Spanned<String>,
+        size: usize,
+    ) -> Result<Self, LabeledError> {
+        let span = path.span;
+        let path = Path::new(&engine.get_current_dir()?)
+            .join(path.item)
+            .into_spanned(span);
+        let mut history = Self {
+            path,
+            size,
+            entries: Vec::new(),
+        };
+        let content = match std::fs::read_to_string(&history.path.item) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(history),
+            Err(err) => return Err(history.io_error(err)),
+        };
+        history.entries = if history.is_nuon() {
+            let entries = call_decl(
+                engine,
+                "from nuon",
+                EvaluatedCall::new(span),
+                PipelineData::Value(Value::string(content, span), None),
+            )?
+            .into_value(span)?;
+            match entries {
+                Value::List { .. } => entries.into_list()?,
+                Value::Nothing { .. } => Vec::new(),
+                _ => {
+                    return Err(LabeledError::new("Invalid history file")
+                        .with_label("expected a list of {query, time} records", span));
+                }
+            }
+        } else {
+            content
+                .lines()
+                .map(|line| Value::string(line, span))
+                .collect()
+        };
+        Ok(history)
+    }
+
+    pub fn queries(&self) -> Vec<String> {
+        self.entries
+            .iter()
+            .filter_map(|entry| Some(entry_query(entry)?.to_owned()))
+            .collect()
+    }
+
+    /// Add the query to the end of the history, and write it back to the file.
+    #[allow(clippy::result_large_err)]
+    pub fn save(mut self, engine: &EngineInterface, query: &str) -> Result<(), LabeledError> {
+        let span = self.path.span;
+        let entry = if self.is_nuon() {
+            Value::record(
+                record! {
+                    "query" => Value::string(query, span),
+                    "time" => Value::date(chrono::Local::now().fixed_offset(), span),
+                },
+                span,
+            )
+        } else {
+            Value::string(query, span)
+        };
+        let mut entries = std::mem::take(&mut self.entries);
+        if !push_entry(&mut entries, entry, self.size) {
+            return Ok(());
+        }
+        let content = if self.is_nuon() {
+            call_decl(
+                engine,
+                "to nuon",
+                EvaluatedCall::new(span)
+                    .with_named("indent".into_spanned(span), Value::int(2, span)),
+                PipelineData::Value(Value::list(entries, span), None),
+            )?
+            .collect_string("", &*engine.get_config()?)?
+        } else {
+            entries
+                .iter()
+                .filter_map(entry_query)
+                .collect::<Vec<_>>()
+                .join("\n")
+        };
+        std::fs::write(&self.path.item, content).map_err(|err| self.io_error(err))
+    }
+
+    fn is_nuon(&self) -> bool {
+        self.path.item.extension().is_some_and(|ext| ext == "nuon")
+    }
+
+    fn io_error(&self, err: std::io::Error) -> LabeledError {
+        LabeledError::new(format!(
+            "Cannot access history file {}",
+            self.path.item.display()
+        ))
+        .with_label(err.to_string(), self.path.span)
+    }
+}
+
+fn entry_query(entry: &Value) -> Option<&str> {
+    match entry {
+        Value::String { val, .. } => Some(val),
+        Value::Record { val, .. } => val.get("query")?.as_str().ok(),
+        _ => None,
+    }
+}
+
+/// Returns `false` if the entry was not added - either because its query is empty or because it
+/// is the same as the last one.
+fn push_entry(entries: &mut Vec<Value>, entry: Value, size: usize) -> bool {
+    let Some(query) = entry_query(&entry) else {
+        return false;
+    };
+    if query.trim().is_empty() || entries.last().and_then(entry_query) == Some(query) {
+        return false;
+    }
+    entries.push(entry);
+    let excess = entries.len().saturating_sub(size);
+    entries.drain(..excess);
+    true
+}
+
+#[allow(clippy::result_large_err)]
+fn call_decl(
+    engine: &EngineInterface,
+    name: &str,
+    call: EvaluatedCall,
+    input: PipelineData,
+) -> Result<PipelineData, ShellError> {
+    let decl = engine
+        .find_decl(name)?
+        .ok_or_else(|| ShellError::CommandNotFound {
+            span: Span::unknown(),
+        })?;
+    engine.call_decl(decl, call, input, true, false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queries(entries: &[Value]) -> Vec<&str> {
+        entries.iter().filter_map(entry_query).collect()
+    }
+
+    #[test]
+    fn history_is_trimmed_to_its_size() {
+        let mut entries = vec![Value::test_string("a"), Value::test_string("b")];
+        assert!(push_entry(&mut entries, Value::test_string("c"), 2));
+        assert_eq!(queries(&entries), ["b", "c"]);
+    }
+
+    #[test]
+    fn empty_and_repeated_queries_are_not_added() {
+        let mut entries = vec![Value::test_record(
+            record! { "query" => Value::test_string("a") },
+        )];
+        assert!(!push_entry(&mut entries, Value::test_string(""), 10));
+        assert!(!push_entry(&mut entries, Value::test_string("a"), 10));
+        assert!(push_entry(&mut entries, Value::test_string("b"), 10));
+        assert_eq!(queries(&entries), ["a", "b"]);
+    }
+}
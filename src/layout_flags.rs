@@ -0,0 +1,266 @@
+//! `--preview-window`, `--margin` and `--height`, which the regular `sk` receives as strings.
+//!
+//! Here they can also be given as records (or ints), and invalid values are reported instead of
+//! being silently ignored.
+
+use nu_protocol::{LabeledError, Record, ShellError, Value};
+use skim::tui::{Direction, Size, options::PreviewLayout};
+
+const DIRECTIONS: &str = "up|down|left|right";
+
+/// A string like `right:50%:wrap`, or a record like `{position: right, size: 50%, wrap: true}`.
+#[allow(clippy::result_large_err)]
+pub fn parse_preview_window(value: &Value) -> Result<PreviewLayout, LabeledError> {
+    let mut layout = PreviewLayout::default();
+    match value {
+        Value::String { val, .. } => {
+            for part in val.split(':').filter(|part| !part.is_empty()) {
+                match part {
+                    "hidden" => layout.hidden = true,
+                    "nohidden" => layout.hidden = false,
+                    "wrap" => layout.wrap = true,
+                    "nowrap" => layout.wrap = false,
+                    "pty" => layout.pty = true,
+                    "nopty" => layout.pty = false,
+                    _ if part.starts_with('+') => layout.offset = Some(part.to_owned()),
+                    _ => {
+                        if let Ok(direction) = Direction::try_from(part) {
+                            layout.direction = direction;
+                        } else if let Ok(size) = Size::try_from(part) {
+                            layout.size = size;
+                        } else {
+                            return Err(LabeledError::new("Invalid preview window")
+                                .with_label(format!("`{part}` is not a preview window option"), value.span())
+                                .with_help(format!("Expected {DIRECTIONS}, a size like 50% or 20, an offset like +3, or [no]hidden/[no]wrap")));
+                        }
+                    }
+                }
+            }
+        }
+        Value::Record { val, .. } => {
+            for (key, field) in val.iter() {
+                match key.as_str() {
+                    "position" => {
+                        let position = field.as_str()?;
+                        layout.direction = Direction::try_from(position).map_err(|_| {
+                            LabeledError::from(ShellError::InvalidValue {
+                                valid: format!("[{DIRECTIONS}]"),
+                                actual: position.to_owned(),
+                                span: field.span(),
+                            })
+                        })?;
+                    }
+                    "size" => layout.size = parse_size(field)?,
+                    "hidden" => layout.hidden = field.as_bool()?,
+                    "wrap" => layout.wrap = field.as_bool()?,
+                    "offset" => {
+                        layout.offset = Some(match field {
+                            Value::Int { val, .. } => format!("+{val}"),
+                            _ => {
+                                let offset = field.as_str()?;
+                                if offset.starts_with('+') {
+                                    offset.to_owned()
+                                } else {
+                                    format!("+{offset}")
+                                }
+                            }
+                        })
+                    }
+                    _ => {
+                        return Err(unknown_key(
+                            "preview window",
+                            key,
+                            field,
+                            "position, size, hidden, wrap, offset",
+                        ));
+                    }
+                }
+            }
+        }
+        _ => return Err(unexpected_type(value, "a string or a record")),
+    }
+    Ok(layout)
+}
+
+/// A string like `1,2` (like in the regular `sk`), an int for all the sides, or a record like
+/// `{top: 1, left: 10%}`. Returns the string that skim expects.
+#[allow(clippy::result_large_err)]
+pub fn parse_margin(value: &Value) -> Result<String, LabeledError> {
+    let sides = match value {
+        Value::Int { .. } => vec![margin_side(value)?],
+        Value::String { val, .. } => val
+            .split(',')
+            .map(|side| margin_side(&Value::string(side.trim(), value.span())))
+            .collect::<Result<Vec<_>, _>>()?,
+        Value::Record { val, .. } => return margin_from_record(val),
+        _ => return Err(unexpected_type(value, "a string, an int or a record")),
+    };
+    // Like in CSS: all, vertical/horizontal, top/horizontal/bottom, or each side
+    let [top, right, bottom, left] = match sides.as_slice() {
+        [all] => [all, all, all, all],
+        [vertical, horizontal] => [vertical, horizontal, vertical, horizontal],
+        [top, horizontal, bottom] => [top, horizontal, bottom, horizontal],
+        [top, right, bottom, left] => [top, right, bottom, left],
+        _ => {
+            return Err(LabeledError::new("Invalid margin").with_label(
+                format!("expected 1 to 4 sides, got {}", sides.len()),
+                value.span(),
+            ));
+        }
+    };
+    Ok(format!("{top},{right},{bottom},{left}"))
+}
+
+#[allow(clippy::result_large_err)]
+fn margin_from_record(record: &Record) -> Result<String, LabeledError> {
+    let mut sides: [String; 4] = std::array::from_fn(|_| "0".to_owned());
+    for (key, field) in record.iter() {
+        let index = match key.as_str() {
+            "top" => 0,
+            "right" => 1,
+            "bottom" => 2,
+            "left" => 3,
+            _ => {
+                return Err(unknown_key(
+                    "margin",
+                    key,
+                    field,
+                    "top, right, bottom, left",
+                ));
+            }
+        };
+        sides[index] = margin_side(field)?;
+    }
+    Ok(sides.join(","))
+}
+
+/// A number of cells or a percentage.
+#[allow(clippy::result_large_err)]
+fn margin_side(value: &Value) -> Result<String, LabeledError> {
+    match value {
+        Value::Int { val, .. } if 0 <= *val => Ok(val.to_string()),
+        _ => match value.as_str() {
+            Ok(side) if matches!(Size::try_from(side), Ok(Size::Fixed(_) | Size::Percent(_))) => {
+                Ok(side.to_owned())
+            }
+            _ => Err(LabeledError::new("Invalid margin").with_label(
+                "expected a number of cells or a percentage, like 2 or 10%",
+                value.span(),
+            )),
+        },
+    }
+}
+
+/// An int for the number of lines, or a string like `40%` (like in the regular `sk`).
+#[allow(clippy::result_large_err)]
+pub fn parse_height(value: &Value) -> Result<String, LabeledError> {
+    Ok(parse_size(value)?.to_string())
+}
+
+#[allow(clippy::result_large_err)]
+fn parse_size(value: &Value) -> Result<Size, LabeledError> {
+    let invalid = |message: String| {
+        LabeledError::new("Invalid size")
+            .with_label(message, value.span())
+            .with_help("Expected a number of lines, a percentage like 40%, or a negative number like -3 for all the lines but 3")
+    };
+    match value {
+        Value::Int { val, .. } => {
+            let lines = u16::try_from(val.unsigned_abs())
+                .map_err(|_| invalid(format!("{val} is too big")))?;
+            Ok(if *val < 0 {
+                Size::Neg(lines)
+            } else {
+                Size::Fixed(lines)
+            })
+        }
+        Value::String { val, .. } => {
+            Size::try_from(val.as_str()).map_err(|err| invalid(err.to_string()))
+        }
+        _ => Err(unexpected_type(value, "an int or a string")),
+    }
+}
+
+fn unknown_key(what: &str, key: &str, field: &Value, valid: &str) -> LabeledError {
+    LabeledError::new(format!("Invalid {what}"))
+        .with_label(format!("unknown key `{key}`"), field.span())
+        .with_help(format!("Valid keys are {valid}"))
+}
+
+fn unexpected_type(value: &Value, expected: &str) -> LabeledError {
+    ShellError::CantConvert {
+        to_type: expected.to_owned(),
+        from_type: value.get_type().to_string(),
+        span: value.span(),
+        help: None,
+    }
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use nu_protocol::record;
+
+    use super::*;
+
+    #[test]
+    fn preview_window_strings_and_records_are_equivalent() {
+        let from_string = parse_preview_window(&Value::test_string("left:30%:wrap:+3")).unwrap();
+        let from_record = parse_preview_window(&Value::test_record(record! {
+            "position" => Value::test_string("left"),
+            "size" => Value::test_string("30%"),
+            "wrap" => Value::test_bool(true),
+            "offset" => Value::test_int(3),
+        }))
+        .unwrap();
+        for layout in [from_string, from_record] {
+            assert_eq!(layout.direction, Direction::Left);
+            assert_eq!(layout.size, Size::Percent(30));
+            assert!(layout.wrap);
+            assert!(!layout.hidden);
+            assert_eq!(layout.offset.as_deref(), Some("+3"));
+        }
+
+        assert!(parse_preview_window(&Value::test_string("left:sideways")).is_err());
+        assert!(parse_preview_window(&Value::test_string("right:150%")).is_err());
+        assert!(
+            parse_preview_window(&Value::test_record(record! {
+                "position" => Value::test_string("sideways"),
+            }))
+            .is_err()
+        );
+        assert!(
+            parse_preview_window(&Value::test_record(record! {
+                "direction" => Value::test_string("left"),
+            }))
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn margins_are_expanded_to_all_sides() {
+        assert_eq!(parse_margin(&Value::test_int(1)).unwrap(), "1,1,1,1");
+        assert_eq!(
+            parse_margin(&Value::test_string("1, 10%")).unwrap(),
+            "1,10%,1,10%"
+        );
+        assert_eq!(
+            parse_margin(&Value::test_record(record! {
+                "top" => Value::test_int(2),
+                "left" => Value::test_string("5%"),
+            }))
+            .unwrap(),
+            "2,0,0,5%"
+        );
+        assert!(parse_margin(&Value::test_string("1,2,3,4,5")).is_err());
+        assert!(parse_margin(&Value::test_string("wide")).is_err());
+    }
+
+    #[test]
+    fn heights_are_validated() {
+        assert_eq!(parse_height(&Value::test_int(20)).unwrap(), "20");
+        assert_eq!(parse_height(&Value::test_string("40%")).unwrap(), "40%");
+        assert_eq!(parse_height(&Value::test_int(-3)).unwrap(), "-3");
+        assert!(parse_height(&Value::test_string("tall")).is_err());
+    }
+}
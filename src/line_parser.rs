@@ -0,0 +1,166 @@
+//! `--parse`, which turns lines of text into records - like piping them through Nushell's `parse`.
+
+use std::sync::Arc;
+
+use nu_protocol::{LabeledError, Record, Spanned, Value};
+use regex::Regex;
+
+pub struct LineParser {
+    regex: Regex,
+}
+
+impl LineParser {
+    /// A pattern like `{file}:{line}:{text}` (like in `parse`), or a regex with named groups (like in
+    /// `parse --regex`).
+    #[allow(clippy::result_large_err)]
+    pub fn new(pattern: &Spanned<String>) -> Result<Self, LabeledError> {
+        let invalid =
+            |err: String| LabeledError::new("Invalid parse pattern").with_label(err, pattern.span);
+        let regex = if pattern.item.contains("(?P<") || pattern.item.contains("(?<") {
+            pattern.item.clone()
+        } else {
+            pattern_to_regex(&pattern.item).map_err(invalid)?
+        };
+        let regex = Regex::new(&regex).map_err(|err| invalid(err.to_string()))?;
+        if regex.capture_names().flatten().next().is_none() {
+            return Err(invalid(
+                "no columns - expected a pattern like {name}:{value}".to_owned(),
+            ));
+        }
+        Ok(Self { regex })
+    }
+
+    /// Strings that don't match are dropped (like in `parse`). Other values are kept as is.
+    pub fn parse(&self, value: Value) -> Option<Value> {
+        let Value::String { val, .. } = &value else {
+            return Some(value);
+        };
+        let span = value.span();
+        let captures = self.regex.captures(val)?;
+        let record = self
+            .regex
+            .capture_names()
+            .flatten()
+            .map(|name| {
+                let capture = captures.name(name).map_or("", |capture| capture.as_str());
+                (name.to_owned(), Value::string(capture, span))
+            })
+            .collect::<Record>();
+        Some(Value::record(record, span))
+    }
+}
+
+/// Number the values by their position in the input before `--parse` drops the lines that don't
+/// match - so that `--index` still refers to the input.
+pub fn numbered_values(
+    values: impl Iterator<Item = Value> + Send + 'static,
+    parser: Option<Arc<LineParser>>,
+) -> impl Iterator<Item = (usize, Value)> + Send + 'static {
+    values
+        .enumerate()
+        .filter_map(move |(index, value)| match &parser {
+            Some(parser) => Some((index, parser.parse(value)?)),
+            None => Some((index, value)),
+        })
+}
+
+/// Like in `parse`, `{name}` captures a column, `{_}` skips text and `{{` is a literal `{`.
+fn pattern_to_regex(pattern: &str) -> Result<String, String> {
+    let mut regex = r"(?s)\A".to_owned();
+    let mut literal = String::new();
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '{' {
+            literal.push(c);
+            continue;
+        }
+        if chars.next_if_eq(&'{').is_some() {
+            literal.push('{');
+            continue;
+        }
+        regex.push_str(&regex::escape(&std::mem::take(&mut literal)));
+        let mut column = String::new();
+        loop {
+            match chars.next() {
+                Some('}') => break,
+                Some(c) => column.push(c),
+                None => return Err("found `{` without a closing `}`".to_owned()),
+            }
+        }
+        if column == "_" {
+            regex.push_str("(?:.*?)");
+        } else {
+            regex.push_str(&format!("(?P<{column}>.*?)"));
+        }
+    }
+    regex.push_str(&regex::escape(&literal));
+    regex.push_str(r"\z");
+    Ok(regex)
+}
+
+#[cfg(test)]
+mod tests {
+    use nu_protocol::{IntoSpanned, Span, record};
+
+    use super::*;
+
+    fn parser(pattern: &str) -> Result<LineParser, LabeledError> {
+        LineParser::new(&pattern.to_owned().into_spanned(Span::test_data()))
+    }
+
+    #[test]
+    fn patterns_are_parsed_like_in_nushell() {
+        let parser = parser("{file}:{line}:{_}:{text}").unwrap();
+        assert_eq!(
+            parser.parse(Value::test_string("src/main.rs:12:5:fn main() {")),
+            Some(Value::test_record(record! {
+                "file" => Value::test_string("src/main.rs"),
+                "line" => Value::test_string("12"),
+                "text" => Value::test_string("fn main() {"),
+            }))
+        );
+        assert_eq!(parser.parse(Value::test_string("no colons")), None);
+        assert_eq!(parser.parse(Value::test_int(3)), Some(Value::test_int(3)));
+    }
+
+    #[test]
+    fn dropped_lines_keep_their_positions() {
+        let lines = ["a:1", "no colons", "b:2"].map(Value::test_string);
+        let parser = Arc::new(parser("{name}:{value}").unwrap());
+        let numbered = numbered_values(lines.into_iter(), Some(parser)).collect::<Vec<_>>();
+        assert_eq!(
+            numbered,
+            [
+                (
+                    0,
+                    Value::test_record(record! {
+                        "name" => Value::test_string("a"),
+                        "value" => Value::test_string("1"),
+                    })
+                ),
+                (
+                    2,
+                    Value::test_record(record! {
+                        "name" => Value::test_string("b"),
+                        "value" => Value::test_string("2"),
+                    })
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn regexes_with_named_groups_are_used_as_is() {
+        let parser = parser(r"^(?<hash>[0-9a-f]+) (?<subject>.*)$").unwrap();
+        assert_eq!(
+            parser.parse(Value::test_string("1a2b3c Fix {the} bug")),
+            Some(Value::test_record(record! {
+                "hash" => Value::test_string("1a2b3c"),
+                "subject" => Value::test_string("Fix {the} bug"),
+            }))
+        );
+        assert!(self::parser("{file").is_err());
+        assert!(self::parser("no columns").is_err());
+        assert!(self::parser("(?<unclosed").is_err());
+    }
+}
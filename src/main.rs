@@ -1,29 +1,56 @@
 mod cli_arguments;
+mod closure_binding;
 mod command_collector;
 mod command_context;
+mod format_cache;
+mod history;
+mod layout_flags;
+mod line_parser;
+mod nu_defaults;
 mod nu_item;
+mod option_kinds;
+mod option_specs;
+mod pickers;
 mod predicate_based_selector;
+mod preview_cache;
+mod sk_defaults;
+mod skim_colors;
+mod table_layout;
 
 use cli_arguments::CliArguments;
-use command_collector::NuCommandCollector;
+use command_collector::{NuCommandCollector, pipeline_values, send_values};
 use command_context::CommandContext;
+use line_parser::{LineParser, numbered_values};
 use nu_item::NuItem;
 use nu_plugin::{EngineInterface, EvaluatedCall};
 use nu_plugin::{MsgPackSerializer, Plugin, PluginCommand, serve_plugin};
 use nu_protocol::{
-    Category, LabeledError, ListStream, PipelineData, Record, ShellError, Signals, Signature,
-    SyntaxShape, Type, Value,
+    Category, LabeledError, ListStream, PipelineData, Record, ShellError, Signals, Signature, Span,
+    Spanned, SyntaxShape, Type, Value, record,
 };
+use option_specs::short;
+use preview_cache::PreviewCache;
+use skim::field::FieldRange;
 use skim::prelude::*;
 use skim::tui::event::Action;
+use table_layout::TableLayout;
 
 use self::command_context::MapperFlag;
 
+const DEFAULT_PREVIEW_CACHE_SIZE: usize = 100;
+
 pub struct SkimPlugin;
 
 impl Plugin for SkimPlugin {
     fn commands(&self) -> Vec<Box<dyn PluginCommand<Plugin = Self>>> {
-        vec![Box::new(Sk)]
+        vec![
+            Box::new(Sk),
+            Box::new(pickers::SkFiles),
+            Box::new(pickers::SkHistory),
+            Box::new(pickers::SkEnv),
+            Box::new(pickers::SkColumns),
+            Box::new(sk_defaults::SkDefaults),
+        ]
     }
 
     fn version(&self) -> String {
@@ -41,46 +68,15 @@ impl PluginCommand for Sk {
     }
 
     fn signature(&self) -> Signature {
-        let signature = {
+        add_sk_flags(
             Signature::build(self.name())
                 .input_output_type(
-                    Type::one_of([
-                        Type::Nothing,
-                        Type::List(Type::Any.into()),
-                    ]),
-                    Type::one_of([
-                        Type::List(Type::Any.into()),
-                        Type::Any,
-                    ]),
+                    Type::one_of([Type::Nothing, Type::List(Type::Any.into())]),
+                    Type::one_of([Type::List(Type::Any.into()), Type::Any]),
                 )
                 .category(Category::Filters)
-                .filter()
-                .named(
-                    "format",
-                    SyntaxShape::OneOf([
-                        SyntaxShape::Closure(Some(vec![])),
-                        SyntaxShape::CellPath,
-                    ].into()),
-                    "Modify the string to display",
-                    Some('f'),
-                )
-                .named(
-                    "preview",
-                    SyntaxShape::OneOf([
-                        SyntaxShape::Closure(Some(vec![])),
-                        SyntaxShape::CellPath,
-                    ].into()),
-                    "Generate a preview",
-                    Some('p'),
-                )
-                .named(
-                    "cmd",
-                    SyntaxShape::Closure(Some(vec![SyntaxShape::String])),
-                    "Command to invoke dynamically. A closure that receives the command query as its argument",
-                    Some('c'),
-                )
-        };
-        CliArguments::add_to_signature(signature)
+                .filter(),
+        )
     }
 
     fn description(&self) -> &str {
@@ -92,147 +88,420 @@ impl PluginCommand for Sk {
         _plugin: &SkimPlugin,
         engine: &EngineInterface,
         call: &EvaluatedCall,
-        mut input: PipelineData,
+        input: PipelineData,
     ) -> Result<PipelineData, LabeledError> {
-        let span = call.head;
+        run_sk(engine, call, input, PickerDefaults::default())
+    }
+}
+
+/// Add the flags of `sk`, which the picker commands share.
+pub fn add_sk_flags(signature: Signature) -> Signature {
+    let signature = {
+        signature
+            .named(
+                "format",
+                SyntaxShape::OneOf([
+                    SyntaxShape::Closure(Some(vec![])),
+                    SyntaxShape::CellPath,
+                ].into()),
+                "Modify the string to display",
+                short("format"),
+            )
+            .switch(
+                "table",
+                "Display records as aligned columns, with the column names as the header",
+                short("table"),
+            )
+            .named(
+                "nth",
+                SyntaxShape::List(Box::new(SyntaxShape::CellPath)),
+                "Match only against these fields of the items (still displaying the entire --format)",
+                short("nth"),
+            )
+            .named(
+                "preview",
+                SyntaxShape::OneOf([
+                    SyntaxShape::Closure(Some(vec![SyntaxShape::Record(Default::default())])),
+                    SyntaxShape::CellPath,
+                ].into()),
+                "Generate a preview. A closure can take a {width, height, query, cmd_query, current_index, selected_count} record parameter",
+                short("preview"),
+            )
+            .named(
+                "preview-cache-size",
+                SyntaxShape::Int,
+                "How many rendered previews to keep, so that they don't need to be generated again (default: 100)",
+                None,
+            )
+            .switch(
+                "keep-order",
+                "Return the selected items in the order of the input, instead of the order they were selected in",
+                None,
+            )
+            .switch(
+                "index",
+                "Return the positions of the selected items in the input, instead of the items themselves",
+                None,
+            )
+            .switch(
+                "with-meta",
+                "Wrap each selected item in a record with its value, index, score and the queries",
+                None,
+            )
+            .named(
+                "parse",
+                SyntaxShape::String,
+                "Turn string items (like the lines of an external command's output) into records, using a pattern like {file}:{line}:{text} or a regex with named groups, like `parse`",
+                None,
+            )
+            .named(
+                "cmd",
+                SyntaxShape::Closure(Some(vec![SyntaxShape::String])),
+                "Command to invoke dynamically. A closure that receives the command query as its argument",
+                short("cmd"),
+            )
+    };
+    CliArguments::add_to_signature(signature)
+}
+
+/// What a picker command shows when `--format` and `--preview` are not given.
+#[derive(Default)]
+pub struct PickerDefaults {
+    pub format: Option<MapperFlag>,
+    pub preview: Option<MapperFlag>,
+    /// Select multiple items even without `--multi`.
+    pub multi: bool,
+    /// Flags the picker can't honour - whether they're passed or come from `SKIM_NU_DEFAULTS`.
+    pub unsupported: Option<UnsupportedFlags>,
+}
+
+pub struct UnsupportedFlags {
+    pub command: &'static str,
+    pub flags: &'static [&'static str],
+    /// Why they're not supported.
+    pub reason: &'static str,
+}
+
+impl PickerDefaults {
+    /// The flags that are set by the picker, rather than by `SKIM_NU_DEFAULTS`.
+    fn flags(&self) -> Vec<&'static str> {
+        [
+            ("format", self.format.is_some()),
+            ("preview", self.preview.is_some()),
+        ]
+        .into_iter()
+        .filter_map(|(flag, set)| set.then_some(flag))
+        .collect()
+    }
+}
 
-        let pipeline_metadata = input.take_metadata();
+/// Run skim on the input, like `sk` does.
+pub fn run_sk(
+    engine: &EngineInterface,
+    call: &EvaluatedCall,
+    mut input: PipelineData,
+    defaults: PickerDefaults,
+) -> Result<PipelineData, LabeledError> {
+    let span = call.head;
+    let call = &nu_defaults::with_nu_defaults(engine, call, &defaults.flags())?;
+    if let Some(unsupported) = &defaults.unsupported {
+        for flag in unsupported.flags {
+            if let Some(flag_value) = call.get_flag_value(flag) {
+                return Err(LabeledError::new(format!(
+                    "`--{flag}` is not supported by `{}`",
+                    unsupported.command
+                ))
+                .with_label(unsupported.reason, flag_value.span()));
+            }
+        }
+    }
 
-        let cli_arguments = CliArguments::new(call, engine)?;
-        let mut skim_options = cli_arguments.to_skim_options();
+    let pipeline_metadata = input.take_metadata();
 
-        let mut command_context = CommandContext::new(engine)?;
-        command_context.format = call.get_flag("format")?.unwrap_or(MapperFlag::None);
+    let cli_arguments = CliArguments::new(call, engine)?;
+    let mut skim_options = cli_arguments.to_skim_options();
+    if defaults.multi {
+        skim_options.multi = true;
+        skim_options.no_multi = false;
+    }
+
+    let parser = call
+        .get_flag::<Spanned<String>>("parse")?
+        .map(|pattern| LineParser::new(&pattern).map(Arc::new))
+        .transpose()?;
+    // The values with their positions in the input
+    let mut entries: Option<Box<dyn Iterator<Item = (usize, Value)> + Send>> = match input {
+        PipelineData::Empty => None,
+        input => match pipeline_values(input) {
+            Some(values) => Some(Box::new(numbered_values(values, parser.clone()))),
+            None => return Ok(PipelineData::empty()),
+        },
+    };
+
+    let mut command_context = CommandContext::new(engine)?;
+    command_context.format = call
+        .get_flag("format")?
+        .or(defaults.format)
+        .unwrap_or(MapperFlag::None);
+    if call.get_flag_value("cmd").is_some() || !cli_arguments.reload_closures().is_empty() {
+        command_context.cache_formats();
+    }
+    command_context.nth = call.get_flag("nth")?.unwrap_or_default();
+    if !skim_options.with_nth.is_empty() {
+        command_context.with_nth = Some((
+            skim_options.delimiter.clone(),
+            skim_options
+                .with_nth
+                .iter()
+                .filter_map(|range| FieldRange::from_str(range))
+                .collect(),
+        ));
+    }
 
-        if let Some(preview) = call.get_flag("preview")? {
-            command_context.preview = preview;
-            skim_options.preview = Some("".to_owned());
+    // What `--format` returned for the first items, so that it's not called for them again
+    let mut formatted_sample = Vec::new();
+    if call.has_flag("table")?
+        && let Some(mut numbered) = entries.take()
+    {
+        // The widths of the columns are decided by a sample of the items, so it must be taken
+        // out of the stream before the context is shared with the reader thread.
+        let sample = numbered
+            .by_ref()
+            .take(table_layout::SAMPLE_SIZE)
+            .collect::<Vec<_>>();
+        formatted_sample = sample
+            .iter()
+            .map(|(_, value)| command_context.format(value).into_owned())
+            .collect::<Vec<_>>();
+        command_context.table =
+            TableLayout::from_sample(&formatted_sample, &command_context.nu_config);
+        if let Some(table) = &command_context.table
+            && skim_options.header.is_none()
+        {
+            skim_options.header = Some(table.header());
         }
+        entries = Some(Box::new(sample.into_iter().chain(numbered)));
+    }
 
-        let command_context = Arc::new(command_context);
-
-        let has_cmd = if let Some(closure) = call.get_flag("cmd")? {
-            // This is a hack to make Skim conjure what it thinks is the actual command but is
-            // actually just the query, which will be sent to as the `cmd` argument to
-            // `NuCommandCollector.invoke`.
-            skim_options.cmd = Some("{q}".to_owned());
-            skim_options.cmd_collector = Rc::new(RefCell::new(NuCommandCollector {
-                context: command_context.clone(),
-                closure,
-            }));
-            true
-        } else {
-            false
-        };
+    if let Some(preview) = call.get_flag("preview")?.or(defaults.preview) {
+        command_context.preview_takes_params = preview.takes_params();
+        command_context.preview = preview;
+        command_context.preview_cache = Some(PreviewCache::new(
+            call.get_flag("preview-cache-size")?
+                .unwrap_or(DEFAULT_PREVIEW_CACHE_SIZE),
+        ));
+        skim_options.preview = Some("".to_owned());
+    }
 
-        let receiver = match input {
-            PipelineData::Empty => {
-                if !has_cmd {
-                    return Err(LabeledError::from_diagnostic(&ShellError::PipelineEmpty {
-                        dst_span: span,
-                    }));
-                }
-                None
-            }
-            PipelineData::Value(_, _) | PipelineData::ListStream(_, _) => {
-                let (sender, receiver) = unbounded::<Vec<Arc<dyn SkimItem>>>();
-                std::thread::spawn(move || {
-                    for entry in input {
-                        if sender
-                            .send(vec![Arc::new(NuItem::new(command_context.clone(), entry))])
-                            .is_err()
-                        {
-                            // Assuming the receiver was closed because the user picked an item
-                            return;
-                        }
-                    }
-                });
-                Some(receiver)
+    let command_context = Arc::new(command_context);
+    let preview_cache_context = command_context.clone();
+
+    let cmd_closure = call.get_flag("cmd")?;
+    let has_cmd = cmd_closure.is_some();
+    if has_cmd {
+        // This is a hack to make Skim conjure what it thinks is the actual command but is
+        // actually just the query, which will be sent to as the `cmd` argument to
+        // `NuCommandCollector.invoke`.
+        skim_options.cmd = Some("{q}".to_owned());
+    }
+    let reload_closures = cli_arguments.reload_closures();
+    if has_cmd || !reload_closures.is_empty() {
+        skim_options.cmd_collector = Rc::new(RefCell::new(NuCommandCollector {
+            context: command_context.clone(),
+            closure: cmd_closure,
+            reload: reload_closures,
+            parser,
+        }));
+    }
+
+    let receiver = match entries {
+        None => {
+            if !has_cmd {
+                return Err(LabeledError::from_diagnostic(&ShellError::PipelineEmpty {
+                    dst_span: span,
+                }));
             }
-            PipelineData::ByteStream(byte_stream, _) => {
-                let Some(lines) = byte_stream.lines() else {
-                    return Ok(PipelineData::empty());
-                };
-                let (sender, receiver) = unbounded::<Vec<Arc<dyn SkimItem>>>();
-                std::thread::spawn(move || {
-                    for line in lines {
-                        if sender
-                            .send(vec![Arc::new(NuItem::new(
-                                command_context.clone(),
-                                match line {
-                                    Ok(text) => Value::string(text, span),
-                                    Err(err) => Value::error(err, span),
-                                },
-                            ))])
-                            .is_err()
-                        {
-                            // Assuming the receiver was closed because the user picked an item
-                            return;
-                        }
-                    }
-                });
-                Some(receiver)
+            None
+        }
+        Some(entries) => {
+            let mut entries = entries.peekable();
+            // Without a `--format`, records are displayed in a notation that includes the
+            // column names anyway - so these column names make a good default header.
+            // `--header-lines` takes the header from the items instead.
+            if skim_options.header.is_none()
+                && skim_options.header_lines == 0
+                && matches!(command_context.format, MapperFlag::None)
+                && let Some((_, Value::Record { val, .. })) = entries.peek()
+            {
+                skim_options.header = Some(val.columns().cloned().collect::<Vec<_>>().join(", "));
             }
-        };
+            let mut formatted_sample = formatted_sample.into_iter();
+            let entries =
+                entries.map(move |(index, value)| (index, value, formatted_sample.next()));
+            let (sender, receiver) = unbounded::<Vec<Arc<dyn SkimItem>>>();
+            std::thread::spawn(move || {
+                send_values(
+                    entries,
+                    move |_, (index, value, formatted)| {
+                        NuItem::with_formatted(command_context.clone(), index, value, formatted)
+                    },
+                    &sender,
+                    None,
+                );
+            });
+            Some(receiver)
+        }
+    };
 
-        let _foreground = engine.enter_foreground()?;
-        let option_expect_is_empty = true; // skim_options.expect.is_empty();
-        let option_multi = skim_options.multi;
-        let skim_output = Skim::run_with(skim_options, receiver).unwrap();
+    // In filter mode there is no TUI, so there is no need to take over the terminal, no key
+    // to expect, and the result is always the list of all the matching items.
+    let option_filter = skim_options.filter.is_some();
+    let _foreground = if option_filter {
+        None
+    } else {
+        Some(engine.enter_foreground()?)
+    };
+    let option_has_expect = cli_arguments.has_expect() && !option_filter;
+    let option_multi = skim_options.multi || option_filter;
+    let option_with_meta = call.has_flag("with-meta")?;
+    let option_keep_order = call.has_flag("keep-order")?;
+    let option_index = call.has_flag("index")?;
+    // Skim reports its internal command here when not in interactive mode
+    let has_cmd_query = skim_options.interactive || skim_options.cmd_query.is_some();
+    let skim_output = run_skim(skim_options, receiver, &preview_cache_context, span)?;
 
-        if skim_output.is_abort {
-            return Ok(PipelineData::empty());
-        }
+    if skim_output.is_abort {
+        return Ok(PipelineData::empty());
+    }
 
-        let mut result = skim_output.selected_items.into_iter().map(|item| {
-            (*item.item)
+    let query = skim_output.query;
+    let cmd_query = has_cmd_query.then_some(skim_output.cmd);
+    // Nothing was typed in filter mode, so there is nothing to remember
+    if !option_filter {
+        cli_arguments.save_histories(engine, &query, cmd_query.as_deref())?;
+    }
+    let mut selected = skim_output
+        .selected_items
+        .iter()
+        .map(|item| {
+            let nu_item = (*item.item)
                 .as_any()
                 .downcast_ref::<NuItem>()
-                .unwrap()
-                .value
-                .clone()
-        });
-        if option_expect_is_empty {
-            if option_multi {
-                Ok(PipelineData::ListStream(
-                    ListStream::new(result, span, Signals::EMPTY),
-                    pipeline_metadata,
-                ))
-            } else {
-                Ok(if let Some(result) = result.next() {
-                    PipelineData::Value(result, pipeline_metadata)
-                } else {
-                    PipelineData::empty()
-                })
-            }
+                .ok_or_else(|| {
+                    LabeledError::new("Skim returned an unexpected item").with_label(
+                        format!("`{}` was not created by this command", item.item.text()),
+                        span,
+                    )
+                })?;
+            Ok((nu_item, item.rank.score))
+        })
+        .collect::<Result<Vec<_>, LabeledError>>()?;
+    if option_keep_order {
+        selected.sort_by_key(|(nu_item, _)| nu_item.index);
+    }
+    let result = selected.into_iter().map(|(nu_item, score)| {
+        let value = if option_index {
+            Value::int(nu_item.index as i64, span)
         } else {
-            let mut record = Record::new();
-            record.push(
-                "action",
-                if let Event::Action(Action::Accept(Some(action))) = skim_output.final_event {
-                    Value::string(action, span)
-                } else {
-                    Value::nothing(span)
-                },
-            );
-
-            record.push(
-                "selected",
-                if option_multi {
-                    Value::list(result.collect(), span)
-                } else if let Some(result) = result.next() {
-                    result
-                } else {
-                    Value::nothing(span)
+            nu_item.value.clone()
+        };
+        if option_with_meta {
+            Value::record(
+                record! {
+                    "value" => value,
+                    "index" => Value::int(nu_item.index as i64, span),
+                    "query" => Value::string(query.clone(), span),
+                    "cmd_query" => cmd_query
+                        .as_ref()
+                        .map_or_else(|| Value::nothing(span), |cmd_query| Value::string(cmd_query, span)),
+                    "score" => Value::int(score.into(), span),
                 },
-            );
-
-            Ok(PipelineData::Value(
-                Value::record(record, span),
+                span,
+            )
+        } else {
+            value
+        }
+    });
+    let mut result = result.collect::<Vec<_>>().into_iter();
+    if !option_has_expect {
+        if option_multi {
+            Ok(PipelineData::ListStream(
+                ListStream::new(result, span, Signals::EMPTY),
                 pipeline_metadata,
             ))
+        } else {
+            Ok(if let Some(result) = result.next() {
+                PipelineData::Value(result, pipeline_metadata)
+            } else {
+                PipelineData::empty()
+            })
         }
+    } else {
+        let mut record = Record::new();
+        record.push(
+            "action",
+            if let Event::Action(Action::Accept(Some(action))) = skim_output.final_event {
+                Value::string(action, span)
+            } else {
+                Value::nothing(span)
+            },
+        );
+
+        record.push(
+            "selected",
+            if option_multi {
+                Value::list(result.collect(), span)
+            } else if let Some(result) = result.next() {
+                result
+            } else {
+                Value::nothing(span)
+            },
+        );
+
+        Ok(PipelineData::Value(
+            Value::record(record, span),
+            pipeline_metadata,
+        ))
+    }
+}
+
+/// Like `Skim::run_with`, but once the TUI is up, previews that were too slow to be shown right
+/// away can make Skim ask for them again.
+#[allow(clippy::result_large_err)]
+fn run_skim(
+    options: SkimOptions,
+    source: Option<SkimItemReceiver>,
+    command_context: &CommandContext,
+    span: Span,
+) -> Result<SkimOutput, LabeledError> {
+    let failed = |err: &dyn std::fmt::Display| {
+        LabeledError::new("Skim failed to run").with_label(format!("{err:#}"), span)
+    };
+    let mut skim = Skim::init(options, source).map_err(|err| failed(&err))?;
+    skim.start();
+    if !skim.should_enter() {
+        // Filter mode, or `--select-1`/`--exit-0` made the choice without the TUI
+        return Ok(SkimOutput {
+            final_event: Event::Action(Action::Accept(None)),
+            is_abort: false,
+            ..skim.output()
+        });
+    }
+    skim.init_tui().map_err(|err| failed(&err))?;
+    if let Some(preview_cache) = &command_context.preview_cache {
+        let events = skim.event_sender();
+        preview_cache.on_ready(move || {
+            let _ = events.try_send(Event::RunPreview);
+        });
     }
+    let runtime = tokio::runtime::Runtime::new().map_err(|err| failed(&err))?;
+    runtime
+        .block_on(async {
+            skim.enter().await?;
+            skim.run().await
+        })
+        .map_err(|err| failed(&err))?;
+    Ok(skim.output())
 }
 
 fn main() {
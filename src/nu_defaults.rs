@@ -0,0 +1,249 @@
+//! `$env.SKIM_NU_DEFAULTS` - a record of default flags. Unlike `SKIM_DEFAULT_OPTIONS`, it can also
+//! hold closures (e.g. `--format` and `--preview`) and records (e.g. `--bind`).
+
+use nu_plugin::{EngineInterface, EvaluatedCall};
+use nu_protocol::{
+    CompareTypes, Config, Flag, IntoSpanned, LabeledError, Record, Signature, Value, did_you_mean,
+};
+
+use crate::add_sk_flags;
+use crate::cli_arguments::OptionDiagnostic;
+
+const ENV_VAR: &str = "SKIM_NU_DEFAULTS";
+
+/// Returns the call with the defaults added as named arguments - except for the flags that were
+/// passed explicitly or that the picker command sets itself (e.g. the `--format` of `sk files`),
+/// which take precedence.
+///
+/// Unknown keys are ignored, since they may be meant for a newer version of the plugin. They are
+/// reported by `sk defaults` instead of here, since a plugin can't show warnings in Nushell and
+/// printing them would end up under Skim's screen.
+#[allow(clippy::result_large_err)]
+pub fn with_nu_defaults(
+    engine: &EngineInterface,
+    call: &EvaluatedCall,
+    picker_flags: &[&str],
+) -> Result<EvaluatedCall, LabeledError> {
+    let Some(defaults) = engine.get_env_var(ENV_VAR)? else {
+        return Ok(call.clone());
+    };
+    merge_defaults(
+        call,
+        &defaults,
+        picker_flags,
+        &add_sk_flags(Signature::build("sk")),
+    )
+}
+
+/// Report whether each key of `SKIM_NU_DEFAULTS` is used - for `sk defaults`.
+#[allow(clippy::result_large_err)]
+pub fn nu_defaults_diagnostics(
+    engine: &EngineInterface,
+) -> Result<Vec<OptionDiagnostic>, LabeledError> {
+    let Some(defaults) = engine.get_env_var(ENV_VAR)? else {
+        return Ok(Vec::new());
+    };
+    diagnose_defaults(
+        &defaults,
+        &add_sk_flags(Signature::build("sk")),
+        &*engine.get_config()?,
+    )
+}
+
+#[allow(clippy::result_large_err)]
+fn merge_defaults(
+    call: &EvaluatedCall,
+    defaults: &Value,
+    picker_flags: &[&str],
+    signature: &Signature,
+) -> Result<EvaluatedCall, LabeledError> {
+    let mut call = call.clone();
+    for (key, value) in defaults_record(defaults)?.iter() {
+        let Ok(flag) = find_flag(key, signature) else {
+            continue;
+        };
+        if call.named.iter().any(|(name, _)| name.item == *key)
+            || picker_flags.contains(&key.as_str())
+        {
+            continue;
+        }
+        if let Err(problem) = check_type(key, value, flag) {
+            return Err(
+                LabeledError::new(format!("Invalid ${ENV_VAR}")).with_label(problem, value.span())
+            );
+        }
+        call.named
+            .push((key.clone().into_spanned(value.span()), Some(value.clone())));
+    }
+    Ok(call)
+}
+
+#[allow(clippy::result_large_err)]
+fn diagnose_defaults(
+    defaults: &Value,
+    signature: &Signature,
+    config: &Config,
+) -> Result<Vec<OptionDiagnostic>, LabeledError> {
+    Ok(defaults_record(defaults)?
+        .iter()
+        .map(|(key, value)| {
+            let (problem, rejected) = match find_flag(key, signature) {
+                Ok(flag) => (check_type(key, value, flag).err(), true),
+                Err(problem) => (Some(problem), false),
+            };
+            OptionDiagnostic {
+                option: key.clone(),
+                value: Some(value.to_expanded_string(", ", config)),
+                rejected: rejected && problem.is_some(),
+                problem,
+            }
+        })
+        .collect())
+}
+
+#[allow(clippy::result_large_err)]
+fn defaults_record(defaults: &Value) -> Result<&Record, LabeledError> {
+    match defaults {
+        Value::Record { val, .. } => Ok(val),
+        _ => Err(LabeledError::new(format!("Invalid ${ENV_VAR}")).with_label(
+            format!("expected a record, got {}", defaults.get_type()),
+            defaults.span(),
+        )),
+    }
+}
+
+fn find_flag<'a>(key: &str, signature: &'a Signature) -> Result<&'a Flag, String> {
+    signature
+        .named
+        .iter()
+        .find(|flag| flag.long == key)
+        .ok_or_else(|| {
+            let flags = signature
+                .named
+                .iter()
+                .map(|flag| flag.long.as_str())
+                .collect::<Vec<_>>();
+            let suggestion = did_you_mean(&flags, key)
+                .map(|suggestion| format!(" - did you mean `{suggestion}`?"))
+                .unwrap_or_default();
+            format!("not a flag of `sk`, so it's ignored{suggestion}")
+        })
+}
+
+fn check_type(key: &str, value: &Value, flag: &Flag) -> Result<(), String> {
+    let expected_type = match &flag.arg {
+        Some(shape) => shape.to_type(),
+        // Switches can be set to `false` to override `SKIM_DEFAULT_OPTIONS`
+        None => nu_protocol::Type::Bool,
+    };
+    if value.is_subtype_of(&expected_type) {
+        Ok(())
+    } else {
+        Err(format!(
+            "`{key}` should be {expected_type}, got {}",
+            value.get_type()
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nu_protocol::{Span, engine::Closure, record};
+
+    use super::*;
+
+    #[test]
+    fn defaults_are_added_unless_passed_explicitly() {
+        let signature = add_sk_flags(Signature::build("sk"));
+        let call = EvaluatedCall::new(Span::test_data()).with_named(
+            "prompt".into_spanned(Span::test_data()),
+            Value::test_string("explicit"),
+        );
+        let closure = Value::test_closure(Closure {
+            block_id: nu_protocol::BlockId::new(0),
+            captures: vec![],
+        });
+        let call = merge_defaults(
+            &call,
+            &Value::test_record(record! {
+                "prompt" => Value::test_string("default"),
+                "multi" => Value::test_bool(true),
+                "format" => closure,
+                "bind" => Value::test_record(record! { "alt-s" => Value::test_string("down") }),
+                "mult" => Value::test_bool(true),
+            }),
+            &[],
+            &signature,
+        )
+        .unwrap();
+        assert_eq!(
+            call.get_flag::<String>("prompt").unwrap().as_deref(),
+            Some("explicit")
+        );
+        assert!(call.has_flag("multi").unwrap());
+        assert!(matches!(
+            call.get_flag_value("format"),
+            Some(Value::Closure { .. })
+        ));
+        assert!(matches!(
+            call.get_flag_value("bind"),
+            Some(Value::Record { .. })
+        ));
+    }
+
+    #[test]
+    fn unknown_and_invalid_defaults_are_diagnosed() {
+        let signature = add_sk_flags(Signature::build("sk"));
+        let diagnostics = diagnose_defaults(
+            &Value::test_record(record! {
+                "prompt" => Value::test_string("default"),
+                "mult" => Value::test_bool(true),
+                "tabstop" => Value::test_string("4"),
+            }),
+            &signature,
+            &Config::default(),
+        )
+        .unwrap();
+        let problems = diagnostics
+            .iter()
+            .map(|diagnostic| (diagnostic.option.as_str(), diagnostic.problem.as_deref()))
+            .collect::<Vec<_>>();
+        assert_eq!(problems[0], ("prompt", None));
+        assert!(problems[1].1.unwrap().contains("did you mean `multi`"));
+        assert!(problems[2].1.unwrap().contains("should be int"));
+        assert!(!diagnostics[1].rejected);
+        assert!(diagnostics[2].rejected);
+    }
+
+    #[test]
+    fn picker_flags_are_not_overridden() {
+        let signature = add_sk_flags(Signature::build("sk"));
+        let call = EvaluatedCall::new(Span::test_data());
+        let call = merge_defaults(
+            &call,
+            &Value::test_record(record! {
+                "preview" => Value::test_string("{}"),
+                "prompt" => Value::test_string("default"),
+            }),
+            &["format", "preview"],
+            &signature,
+        )
+        .unwrap();
+        assert!(call.get_flag_value("preview").is_none());
+        assert!(call.get_flag_value("prompt").is_some());
+    }
+
+    #[test]
+    fn defaults_are_type_checked() {
+        let signature = add_sk_flags(Signature::build("sk"));
+        let call = EvaluatedCall::new(Span::test_data());
+        for invalid in [
+            record! { "multi" => Value::test_string("yes") },
+            record! { "tabstop" => Value::test_string("4") },
+            record! { "expect" => Value::test_string("ctrl-v") },
+        ] {
+            assert!(merge_defaults(&call, &Value::test_record(invalid), &[], &signature).is_err());
+        }
+        assert!(merge_defaults(&call, &Value::test_string("--multi"), &[], &signature).is_err());
+    }
+}
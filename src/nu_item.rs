@@ -3,40 +3,275 @@ use std::ops::Range;
 
 use ansi_to_tui::IntoText;
 use nu_plugin::EvaluatedCall;
+use nu_protocol::ast::PathMember;
 use nu_protocol::shell_error::generic::GenericError;
-use nu_protocol::{IntoSpanned, PipelineData, ShellError, Span as NuSpan, Value};
+use nu_protocol::{
+    Config, IntoSpanned, PipelineData, Record, ShellError, Span as NuSpan, Value, record,
+};
 use ratatui::style::{Color, Style};
 use ratatui::text::{Line, Span};
+use regex::Regex;
+use skim::field::{FieldRange, parse_transform_fields};
 use skim::prelude::*;
 
-use crate::command_context::CommandContext;
+use crate::command_context::{CommandContext, MapperFlag};
+use crate::preview_cache::PREVIEW_PLACEHOLDER;
 
 pub struct NuItem {
     pub context: Arc<CommandContext>,
     pub value: Value,
+    /// The position of the item in the input stream.
+    pub index: usize,
+    /// What Skim matches against. Unless `--nth` is used, this is the same as the displayed text.
     text: String,
     display: Line<'static>,
+    /// The position in `display` of each character of `text`, when `--nth` makes them differ.
+    text_to_display: Option<Vec<Option<usize>>>,
 }
 
 impl NuItem {
-    pub fn new(context: Arc<CommandContext>, value: Value) -> Self {
-        let display = parse_ansi(
-            context
-                .format
-                .map(&context, &value)
-                .to_expanded_string(", ", &context.nu_config),
-        );
-        let text = display
+    pub fn new(context: Arc<CommandContext>, index: usize, value: Value) -> Self {
+        Self::with_formatted(context, index, value, None)
+    }
+
+    /// Like `new`, but `formatted` is what `--format` already returned for the value, if it was
+    /// called.
+    pub fn with_formatted(
+        context: Arc<CommandContext>,
+        index: usize,
+        value: Value,
+        formatted: Option<Value>,
+    ) -> Self {
+        let formatted = match formatted {
+            Some(formatted) => Cow::Owned(formatted),
+            None => context.format(&value),
+        };
+        let formatted = match &context.with_nth {
+            Some((delimiter, fields)) => with_nth_fields(delimiter, fields, formatted),
+            None => formatted,
+        };
+        let (display, table_cells) = match (&context.table, formatted.as_ref()) {
+            (Some(table), Value::Record { val, .. }) => {
+                let (row, cells) = table.format_row(val, &context.nu_config);
+                (parse_ansi(row), Some(cells))
+            }
+            _ => (
+                parse_ansi(formatted.to_expanded_string(", ", &context.nu_config)),
+                None,
+            ),
+        };
+        let display_text: String = display
             .spans
             .iter()
             .map(|span| span.content.as_ref())
             .collect();
+        // Cell-paths can only pick fields out of records and lists - other items (like the lines of
+        // an external command) are matched on what they display
+        let (text, text_to_display) = if !context.nth.is_empty()
+            && matches!(value, Value::Record { .. } | Value::List { .. })
+        {
+            // Where the fields are displayed is known when the row is laid out here - otherwise
+            // they can only be searched for in whatever `--format` returned.
+            let displayed_fields = if let Some(table_cells) = &table_cells {
+                Some(
+                    table_cells
+                        .iter()
+                        .filter_map(|(column, cell)| {
+                            let start = cell.display_start?;
+                            let path = vec![PathKey::Column((*column).to_owned())];
+                            Some((path, start..start + cell.displayed_chars))
+                        })
+                        .collect(),
+                )
+            } else if matches!(context.format, MapperFlag::None) {
+                let expansion = PositionedExpansion::new(&value, &context.nu_config);
+                (expansion.text == display_text).then_some(expansion.positions)
+            } else {
+                None
+            };
+            let fields = context.nth.iter().map(|cell_path| {
+                let text = value
+                    .follow_cell_path(&cell_path.members)
+                    .map(|field| field.to_expanded_string(", ", &context.nu_config))
+                    .unwrap_or_default();
+                let displayed = displayed_fields.as_ref().map(|displayed_fields| {
+                    let path = cell_path
+                        .members
+                        .iter()
+                        .map(PathKey::from)
+                        .collect::<Vec<_>>();
+                    displayed_fields
+                        .iter()
+                        .find(|(displayed_path, _)| *displayed_path == path)
+                        .map(|(_, range)| range.clone())
+                });
+                (text, displayed)
+            });
+            let (text, text_to_display) = map_fields_to_display(fields, &display_text);
+            (text, Some(text_to_display))
+        } else if let Some(table_cells) = table_cells {
+            let (text, text_to_display) =
+                join_fields(table_cells.into_iter().map(|(_, cell)| cell));
+            (text, Some(text_to_display))
+        } else {
+            (display_text, None)
+        };
         Self {
             context,
             value,
+            index,
             text,
             display,
+            text_to_display,
+        }
+    }
+}
+
+/// Like in the regular `sk`, only the `--with-nth` fields of string items are displayed and matched
+/// against - while the item still returns the whole value.
+fn with_nth_fields<'a>(
+    delimiter: &Regex,
+    fields: &[FieldRange],
+    formatted: Cow<'a, Value>,
+) -> Cow<'a, Value> {
+    match formatted.as_ref() {
+        Value::String { val, .. } => Cow::Owned(Value::string(
+            parse_transform_fields(delimiter, val, fields),
+            formatted.span(),
+        )),
+        _ => formatted,
+    }
+}
+
+/// A part of the text to match against, which may be displayed somewhere else in the row.
+pub struct TextField {
+    pub text: String,
+    /// The position of the field's first character in the displayed text, if it's displayed.
+    pub display_start: Option<usize>,
+    /// How many characters of the field are displayed (the rest may have been truncated).
+    pub displayed_chars: usize,
+}
+
+/// Join the fields into the text to match against, remembering where each of their characters is
+/// displayed so that matches can still be highlighted there.
+fn join_fields(fields: impl IntoIterator<Item = TextField>) -> (String, Vec<Option<usize>>) {
+    let mut text = String::new();
+    let mut text_to_display = Vec::new();
+    for field in fields {
+        if !text.is_empty() {
+            text.push(' ');
+            text_to_display.push(None);
+        }
+        text_to_display.extend((0..field.text.chars().count()).map(|offset| {
+            field
+                .display_start
+                .filter(|_| offset < field.displayed_chars)
+                .map(|start| start + offset)
+        }));
+        text.push_str(&field.text);
+    }
+    (text, text_to_display)
+}
+
+/// Join the `--nth` fields into the text to match against. Each field comes with where it's
+/// displayed, if that is known - and otherwise it's located by its first occurrence in the
+/// displayed text.
+fn map_fields_to_display(
+    fields: impl Iterator<Item = (String, Option<Option<Range<usize>>>)>,
+    display_text: &str,
+) -> (String, Vec<Option<usize>>) {
+    join_fields(fields.map(|(field, displayed)| {
+        let displayed = displayed.unwrap_or_else(|| {
+            let byte_index = display_text
+                .find(field.as_str())
+                .filter(|_| !field.is_empty())?;
+            let start = display_text[..byte_index].chars().count();
+            Some(start..start + field.chars().count())
+        });
+        TextField {
+            text: field,
+            display_start: displayed.as_ref().map(|range| range.start),
+            displayed_chars: displayed.map_or(0, |range| range.len()),
+        }
+    }))
+}
+
+/// A step of a cell path, for finding where a nested value is displayed.
+#[derive(Clone, PartialEq)]
+enum PathKey {
+    Column(String),
+    Index(usize),
+}
+
+impl From<&PathMember> for PathKey {
+    fn from(member: &PathMember) -> Self {
+        match member {
+            PathMember::String { val, .. } => Self::Column(val.clone()),
+            PathMember::Int { val, .. } => Self::Index(*val),
+        }
+    }
+}
+
+/// A value expanded like `Value::to_expanded_string` does, along with where each of the values
+/// nested in it ended up - as character ranges.
+struct PositionedExpansion<'a> {
+    config: &'a Config,
+    text: String,
+    chars: usize,
+    path: Vec<PathKey>,
+    positions: Vec<(Vec<PathKey>, Range<usize>)>,
+}
+
+impl<'a> PositionedExpansion<'a> {
+    fn new(value: &Value, config: &'a Config) -> Self {
+        let mut expansion = Self {
+            config,
+            text: String::new(),
+            chars: 0,
+            path: Vec::new(),
+            positions: Vec::new(),
+        };
+        expansion.push_value(value);
+        expansion
+    }
+
+    fn push_str(&mut self, text: &str) {
+        self.text.push_str(text);
+        self.chars += text.chars().count();
+    }
+
+    fn push_value(&mut self, value: &Value) {
+        let start = self.chars;
+        match value {
+            Value::Record { val, .. } => {
+                self.push_str("{");
+                for (index, (column, value)) in val.iter().enumerate() {
+                    if 0 < index {
+                        self.push_str(", ");
+                    }
+                    self.push_str(column);
+                    self.push_str(": ");
+                    self.path.push(PathKey::Column(column.clone()));
+                    self.push_value(value);
+                    self.path.pop();
+                }
+                self.push_str("}");
+            }
+            Value::List { vals, .. } => {
+                self.push_str("[");
+                for (index, value) in vals.iter().enumerate() {
+                    if 0 < index {
+                        self.push_str(", ");
+                    }
+                    self.path.push(PathKey::Index(index));
+                    self.push_value(value);
+                    self.path.pop();
+                }
+                self.push_str("]");
+            }
+            value => self.push_str(&value.to_expanded_string(", ", self.config)),
         }
+        self.positions.push((self.path.clone(), start..self.chars));
     }
 }
 
@@ -100,6 +335,19 @@ impl MatchedChars {
         })
     }
 
+    /// Move the matched positions from the matched text to the displayed text.
+    fn remap(self, text_to_display: &[Option<usize>]) -> Self {
+        let text_indices: Box<dyn Iterator<Item = usize>> = match self {
+            Self::Indices(indices) => Box::new(indices.into_iter()),
+            Self::Range(range) => Box::new(range),
+        };
+        Self::Indices(
+            text_indices
+                .filter_map(|index| text_to_display.get(index).copied().flatten())
+                .collect(),
+        )
+    }
+
     fn contains(&self, char_index: usize) -> bool {
         match self {
             Self::Indices(indices) => indices.contains(&char_index),
@@ -108,7 +356,12 @@ impl MatchedChars {
     }
 }
 
-fn highlight(display: &Line<'_>, text: &str, context: &DisplayContext) -> Line<'static> {
+fn highlight(
+    display: &Line<'_>,
+    text: &str,
+    text_to_display: Option<&[Option<usize>]>,
+    context: &DisplayContext,
+) -> Line<'static> {
     let styled = |content: String, ansi_style: Style, is_match: bool| {
         let style = merge_styles(context.base_style, ansi_style);
         let style = if is_match {
@@ -119,7 +372,13 @@ fn highlight(display: &Line<'_>, text: &str, context: &DisplayContext) -> Line<'
         Span::styled(content, style)
     };
 
-    let Some(matched) = MatchedChars::new(&context.matches, text) else {
+    let Some(matched) = MatchedChars::new(&context.matches, text).map(|matched| {
+        if let Some(text_to_display) = text_to_display {
+            matched.remap(text_to_display)
+        } else {
+            matched
+        }
+    }) else {
         return display
             .spans
             .iter()
@@ -155,46 +414,237 @@ impl SkimItem for NuItem {
     }
 
     fn display(&self, context: DisplayContext) -> Line<'_> {
-        highlight(&self.display, &self.text, &context)
+        highlight(
+            &self.display,
+            &self.text,
+            self.text_to_display.as_deref(),
+            &context,
+        )
     }
 
     fn preview(&self, context: PreviewContext) -> ItemPreview {
-        let preview_result = self.context.preview.map(&self.context, &self.value);
-        if let Ok(preview_result) = preview_result.coerce_string() {
-            return ItemPreview::AnsiText(preview_result);
+        let params = PreviewParams {
+            width: context.width,
+            height: context.height,
+            query: context.query.to_owned(),
+            cmd_query: context.cmd_query.to_owned(),
+            current_index: self.index,
+            selected_count: context.selected_indices.len(),
+        };
+        let Some(cache) = &self.context.preview_cache else {
+            return render_preview(&self.context, &self.value, &params).into();
+        };
+        let key = params.cache_key(self.context.preview_takes_params);
+        let render = {
+            let command_context = self.context.clone();
+            let value = self.value.clone();
+            move || render_preview(&command_context, &value, &params)
+        };
+        cache
+            .get_or_render(key, render)
+            .unwrap_or_else(|| RenderedPreview::new(PREVIEW_PLACEHOLDER.to_owned()))
+            .into()
+    }
+}
+
+/// What the `--preview` closure receives as its parameter, besides the item it gets as input.
+#[derive(Clone, PartialEq)]
+pub struct PreviewParams {
+    pub width: usize,
+    pub height: usize,
+    pub query: String,
+    pub cmd_query: String,
+    /// The position of the item in the input stream.
+    pub current_index: usize,
+    pub selected_count: usize,
+}
+
+impl PreviewParams {
+    /// What the preview of the item depends on. The size of the window is always used - for
+    /// laying out tables and centering the highlighted line - but the rest only matters to
+    /// closures, which receive the parameters.
+    fn cache_key(&self, preview_takes_params: bool) -> Self {
+        if preview_takes_params {
+            return self.clone();
         }
-        let result = self.context.engine.find_decl("table").and_then(
-            #[allow(clippy::result_large_err)]
-            |table_decl| {
-                let table_decl = table_decl.ok_or_else(|| {
-                    ShellError::Generic(GenericError::new(
-                        "`table` decl is empty",
-                        "`table` decl is empty",
-                        NuSpan::unknown(),
-                    ))
-                })?;
-                let as_table = self.context.engine.call_decl(
-                    table_decl,
-                    // TODO: get the actual span
-                    EvaluatedCall::new(NuSpan::unknown()).with_named(
-                        "width".into_spanned(NuSpan::unknown()),
-                        Value::int(context.width as i64, NuSpan::unknown()),
-                    ),
-                    PipelineData::Value((*preview_result).clone(), None),
-                    true,
-                    false,
-                )?;
-                let as_table_text = as_table.collect_string("\n", &self.context.nu_config)?;
-                Ok(as_table_text)
+        Self {
+            query: String::new(),
+            cmd_query: String::new(),
+            selected_count: 0,
+            ..self.clone()
+        }
+    }
+
+    fn to_value(&self, span: NuSpan) -> Value {
+        Value::record(
+            record! {
+                "width" => Value::int(self.width as i64, span),
+                "height" => Value::int(self.height as i64, span),
+                "query" => Value::string(&self.query, span),
+                "cmd_query" => Value::string(&self.cmd_query, span),
+                "current_index" => Value::int(self.current_index as i64, span),
+                "selected_count" => Value::int(self.selected_count as i64, span),
             },
-        );
-        match result {
-            Ok(text) => ItemPreview::AnsiText(text),
-            Err(err) => ItemPreview::AnsiText(err.to_string()),
+            span,
+        )
+    }
+}
+
+#[derive(Clone)]
+pub struct RenderedPreview {
+    text: String,
+    /// The line (1-based, like skim's `+N` preview offset) to show at the top of the window.
+    offset: Option<u16>,
+}
+
+impl RenderedPreview {
+    fn new(text: String) -> Self {
+        Self { text, offset: None }
+    }
+}
+
+impl From<RenderedPreview> for ItemPreview {
+    fn from(preview: RenderedPreview) -> Self {
+        match preview.offset {
+            None => ItemPreview::AnsiText(preview.text),
+            Some(offset) => ItemPreview::AnsiWithPos(
+                preview.text,
+                PreviewPosition {
+                    v_scroll: skim::tui::Size::Fixed(offset.saturating_sub(1)),
+                    ..Default::default()
+                },
+            ),
         }
     }
 }
 
+/// What a preview closure returns to position the preview - a `{text, offset, highlight_line}`
+/// record, where `text` is a string and the line numbers are ints. Any other record (e.g. one with
+/// only a `text` column) is previewed as is.
+struct PositionedPreview<'a> {
+    text: &'a str,
+    offset: Option<i64>,
+    highlight_line: Option<i64>,
+}
+
+impl<'a> PositionedPreview<'a> {
+    const FIELDS: [&'static str; 3] = ["text", "offset", "highlight_line"];
+
+    fn from_record(record: &'a Record) -> Option<Self> {
+        if record
+            .columns()
+            .any(|column| !Self::FIELDS.contains(&column.as_str()))
+        {
+            return None;
+        }
+        let line_number = |field: &str| match record.get(field) {
+            None => Some(None),
+            Some(Value::Int { val, .. }) => Some(Some(*val)),
+            Some(_) => None,
+        };
+        let offset = line_number("offset")?;
+        let highlight_line = line_number("highlight_line")?;
+        if offset.is_none() && highlight_line.is_none() {
+            return None;
+        }
+        Some(Self {
+            text: record.get("text")?.as_str().ok()?,
+            offset,
+            highlight_line,
+        })
+    }
+}
+
+fn render_preview(
+    context: &CommandContext,
+    value: &Value,
+    params: &PreviewParams,
+) -> RenderedPreview {
+    let preview_result =
+        context
+            .preview
+            .map_with_args(context, value, vec![params.to_value(NuSpan::unknown())]);
+    if let Value::Record { val, .. } = preview_result.as_ref()
+        && let Some(positioned) = PositionedPreview::from_record(val)
+    {
+        return render_positioned_preview(positioned, params);
+    }
+    RenderedPreview::new(render_preview_text(context, &preview_result, params))
+}
+
+/// Without an `offset`, the highlighted line is centered.
+fn render_positioned_preview(
+    preview: PositionedPreview,
+    params: &PreviewParams,
+) -> RenderedPreview {
+    let line_number = |line: Option<i64>| Some(line?.clamp(1, u16::MAX.into()) as u16);
+    let offset = line_number(preview.offset);
+    let highlight_line = line_number(preview.highlight_line);
+    let text = match highlight_line {
+        Some(highlight_line) => highlight_preview_line(preview.text, highlight_line.into()),
+        None => preview.text.to_owned(),
+    };
+    let offset = offset.or_else(|| {
+        let half_height = u16::try_from(params.height / 2).unwrap_or(u16::MAX);
+        Some(highlight_line?.saturating_sub(half_height).max(1))
+    });
+    RenderedPreview { text, offset }
+}
+
+fn render_preview_text(context: &CommandContext, value: &Value, params: &PreviewParams) -> String {
+    if let Ok(text) = value.coerce_string() {
+        return text;
+    }
+    let result = context.engine.find_decl("table").and_then(
+        #[allow(clippy::result_large_err)]
+        |table_decl| {
+            let table_decl = table_decl.ok_or_else(|| {
+                ShellError::Generic(GenericError::new(
+                    "`table` decl is empty",
+                    "`table` decl is empty",
+                    NuSpan::unknown(),
+                ))
+            })?;
+            let as_table = context.engine.call_decl(
+                table_decl,
+                // TODO: get the actual span
+                EvaluatedCall::new(NuSpan::unknown()).with_named(
+                    "width".into_spanned(NuSpan::unknown()),
+                    Value::int(params.width as i64, NuSpan::unknown()),
+                ),
+                PipelineData::Value(value.clone(), None),
+                true,
+                false,
+            )?;
+            let as_table_text = as_table.collect_string("\n", &context.nu_config)?;
+            Ok(as_table_text)
+        },
+    );
+    match result {
+        Ok(text) => text,
+        Err(err) => err.to_string(),
+    }
+}
+
+/// Show the line (1-based) in reverse video. Resets inside the line would end the reverse video,
+/// so they are made to keep it.
+fn highlight_preview_line(text: &str, line_number: usize) -> String {
+    text.split('\n')
+        .enumerate()
+        .map(|(index, line)| {
+            if index + 1 == line_number {
+                let line = line
+                    .replace("\x1b[0m", "\x1b[0;7m")
+                    .replace("\x1b[m", "\x1b[0;7m");
+                format!("\x1b[7m{line}\x1b[0m")
+            } else {
+                line.to_owned()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -226,7 +676,7 @@ mod tests {
             .iter()
             .map(|span| span.content.as_ref())
             .collect();
-        let highlighted = highlight(&display, &text, &context(matches));
+        let highlighted = highlight(&display, &text, None, &context(matches));
         // Whatever the styling, Skim clips the line by the character count of `text()`, so
         // the two must always hold the same characters.
         assert_eq!(
@@ -319,6 +769,145 @@ mod tests {
         assert_eq!(text, "ab\ncd");
     }
 
+    #[test]
+    fn nth_matches_are_highlighted_where_the_fields_are_displayed() {
+        let display = parse_ansi("alice - admin - /home/alice".to_owned());
+        let (text, text_to_display) = map_fields_to_display(
+            [("admin".to_owned(), None), ("missing".to_owned(), None)].into_iter(),
+            "alice - admin - /home/alice",
+        );
+        assert_eq!(text, "admin missing");
+        // "dm" in "admin", the separator, and "mi" in "missing" (which is not displayed).
+        let highlighted = highlight(
+            &display,
+            &text,
+            Some(&text_to_display),
+            &context(Matches::CharIndices(vec![1, 2, 5, 8, 9])),
+        );
+        assert_eq!(
+            rendered(&highlighted),
+            [
+                (
+                    "alice - a".to_owned(),
+                    Some(Color::White),
+                    Some(Color::Black)
+                ),
+                ("dm".to_owned(), Some(Color::Red), Some(Color::Black)),
+                (
+                    "in - /home/alice".to_owned(),
+                    Some(Color::White),
+                    Some(Color::Black)
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn nth_fields_are_located_where_they_are_displayed_even_if_values_repeat() {
+        let value = Value::test_record(record! {
+            "name" => Value::test_string("x"),
+            "tags" => Value::test_list(vec![Value::test_string("x"), Value::test_string("y")]),
+        });
+        let config = Config::default();
+        let expansion = PositionedExpansion::new(&value, &config);
+        assert_eq!(expansion.text, value.to_expanded_string(", ", &config));
+        let position = |path: Vec<PathKey>| {
+            expansion
+                .positions
+                .iter()
+                .find(|(displayed_path, _)| *displayed_path == path)
+                .map(|(_, range)| range.clone())
+        };
+        // {name: x, tags: [x, y]}
+        assert_eq!(
+            position(vec![PathKey::Column("name".to_owned())]),
+            Some(7..8)
+        );
+        let second_x = position(vec![PathKey::Column("tags".to_owned()), PathKey::Index(0)]);
+        assert_eq!(second_x, Some(17..18));
+        let (text, text_to_display) = map_fields_to_display(
+            [("x".to_owned(), Some(second_x))].into_iter(),
+            &expansion.text,
+        );
+        assert_eq!(text, "x");
+        assert_eq!(text_to_display, [Some(17)]);
+    }
+
+    #[test]
+    fn only_the_with_nth_fields_of_strings_are_displayed() {
+        let delimiter = Regex::new(":").unwrap();
+        let fields = [FieldRange::from_str("2..").unwrap()];
+        let line = Value::test_string("/usr/bin:bash:login shell");
+        let displayed = with_nth_fields(&delimiter, &fields, Cow::Borrowed(&line));
+        assert_eq!(*displayed, Value::test_string("bash:login shell"));
+        let record = Value::test_record(record! { "a" => Value::test_string("x:y") });
+        assert_eq!(
+            *with_nth_fields(&delimiter, &fields, Cow::Borrowed(&record)),
+            record
+        );
+    }
+
+    #[test]
+    fn highlighted_preview_line_stays_reversed_after_resets() {
+        let text = "first\n\x1b[31mred\x1b[0m rest\nlast";
+        assert_eq!(
+            highlight_preview_line(text, 2),
+            "first\n\x1b[7m\x1b[31mred\x1b[0;7m rest\x1b[0m\nlast",
+        );
+        // Lines out of range are ignored
+        assert_eq!(highlight_preview_line(text, 4), text);
+    }
+
+    #[test]
+    fn only_exact_positioned_preview_records_position_the_preview() {
+        let positioned = |record: Record| {
+            PositionedPreview::from_record(&record).map(|preview| {
+                (
+                    preview.text.to_owned(),
+                    preview.offset,
+                    preview.highlight_line,
+                )
+            })
+        };
+        assert_eq!(
+            positioned(record! {
+                "text" => Value::test_string("a\nb"),
+                "highlight_line" => Value::test_int(2),
+            }),
+            Some(("a\nb".to_owned(), None, Some(2)))
+        );
+        for record in [
+            // Nothing to position
+            record! { "text" => Value::test_string("a") },
+            record! { "text" => Value::test_string("a"), "offset" => Value::test_string("3") },
+            record! { "text" => Value::test_int(1), "offset" => Value::test_int(3) },
+            record! {
+                "text" => Value::test_string("a"),
+                "offset" => Value::test_int(3),
+                "author" => Value::test_string("me"),
+            },
+        ] {
+            assert_eq!(positioned(record), None);
+        }
+    }
+
+    #[test]
+    fn previews_only_depend_on_the_query_if_the_closure_receives_it() {
+        let params = |query: &str| PreviewParams {
+            width: 80,
+            height: 20,
+            query: query.to_owned(),
+            cmd_query: String::new(),
+            current_index: 3,
+            selected_count: 0,
+        };
+        assert!(params("a").cache_key(false) == params("ab").cache_key(false));
+        assert!(params("a").cache_key(true) != params("ab").cache_key(true));
+        let mut resized = params("a");
+        resized.width = 100;
+        assert!(params("a").cache_key(false) != resized.cache_key(false));
+    }
+
     #[test]
     fn reset_does_not_override_the_other_style() {
         let reset = Style::new().fg(Color::Reset).bg(Color::Reset);
@@ -0,0 +1,550 @@
+//! The kinds of the options that `sk` passes on to skim. A kind decides the shape of the flag, and
+//! how its value is parsed - both from the call and from `SKIM_DEFAULT_OPTIONS`.
+//!
+//! The options themselves are declared in `cli_arguments`.
+
+use std::marker::PhantomData;
+
+use clap::ValueEnum;
+use nu_plugin::{EngineInterface, EvaluatedCall};
+use nu_protocol::{
+    FromValue, IntoSpanned, LabeledError, ShellError, Span, Spanned, SyntaxShape, Value,
+    engine::Closure,
+};
+use regex::Regex;
+use skim::field::FieldRange;
+use skim::tui::options::PreviewLayout;
+
+use crate::closure_binding::Bindings;
+use crate::layout_flags;
+use crate::skim_colors;
+
+pub const NOT_IN_ENV: &str = "cannot be set in SKIM_DEFAULT_OPTIONS - use $env.SKIM_NU_DEFAULTS";
+
+pub trait OptionKind {
+    type Value;
+
+    /// Whether the flag takes a value, as opposed to being a switch.
+    const TAKES_VALUE: bool = true;
+
+    fn shape() -> SyntaxShape;
+
+    #[allow(clippy::result_large_err)]
+    fn from_value(value: Value, engine: &EngineInterface) -> Result<Self::Value, LabeledError>;
+
+    /// Parse the value as written in `SKIM_DEFAULT_OPTIONS`.
+    fn parse_str(value: &str) -> Result<Self::Value, String>;
+
+    #[allow(clippy::result_large_err)]
+    fn from_call(
+        call: &EvaluatedCall,
+        long: &str,
+        engine: &EngineInterface,
+    ) -> Result<Option<Self::Value>, LabeledError> {
+        call.get_flag_value(long)
+            .map(|value| Self::from_value(value, engine))
+            .transpose()
+    }
+
+    fn from_env(value: Option<&str>) -> Result<Self::Value, String> {
+        Self::parse_str(value.ok_or_else(|| "missing value".to_owned())?)
+    }
+}
+
+pub struct Switch;
+
+impl OptionKind for Switch {
+    type Value = bool;
+
+    const TAKES_VALUE: bool = false;
+
+    /// Only used for the value of the switch in `$env.SKIM_NU_DEFAULTS`.
+    fn shape() -> SyntaxShape {
+        SyntaxShape::Boolean
+    }
+
+    fn from_value(value: Value, _engine: &EngineInterface) -> Result<bool, LabeledError> {
+        Ok(value.as_bool()?)
+    }
+
+    fn parse_str(_value: &str) -> Result<bool, String> {
+        Err("does not take a value".to_owned())
+    }
+
+    /// Unlike a switch that was not passed, `--multi=false` (or `multi: false` in
+    /// `$env.SKIM_NU_DEFAULTS`) overrides `SKIM_DEFAULT_OPTIONS`.
+    fn from_call(
+        call: &EvaluatedCall,
+        long: &str,
+        _engine: &EngineInterface,
+    ) -> Result<Option<bool>, LabeledError> {
+        if call.named.iter().any(|(name, _)| name.item == long) {
+            Ok(Some(call.has_flag(long)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn from_env(_value: Option<&str>) -> Result<bool, String> {
+        Ok(true)
+    }
+}
+
+pub struct Text;
+
+impl OptionKind for Text {
+    type Value = String;
+
+    fn shape() -> SyntaxShape {
+        SyntaxShape::String
+    }
+
+    fn from_value(value: Value, _engine: &EngineInterface) -> Result<String, LabeledError> {
+        Ok(String::from_value(value)?)
+    }
+
+    fn parse_str(value: &str) -> Result<String, String> {
+        Ok(value.to_owned())
+    }
+}
+
+/// A list of strings, which `SKIM_DEFAULT_OPTIONS` separates with commas or spaces.
+pub struct TextList;
+
+impl OptionKind for TextList {
+    type Value = Vec<String>;
+
+    fn shape() -> SyntaxShape {
+        SyntaxShape::List(Box::new(SyntaxShape::String))
+    }
+
+    fn from_value(value: Value, _engine: &EngineInterface) -> Result<Vec<String>, LabeledError> {
+        Ok(Vec::from_value(value)?)
+    }
+
+    fn parse_str(value: &str) -> Result<Vec<String>, String> {
+        Ok(split_csv_like(value))
+    }
+}
+
+pub struct Count;
+
+impl OptionKind for Count {
+    type Value = usize;
+
+    fn shape() -> SyntaxShape {
+        SyntaxShape::Int
+    }
+
+    fn from_value(value: Value, _engine: &EngineInterface) -> Result<usize, LabeledError> {
+        Ok(usize::from_value(value)?)
+    }
+
+    fn parse_str(value: &str) -> Result<usize, String> {
+        parse_number(value)
+    }
+}
+
+/// A number of lines, which skim keeps in a `u16`.
+pub struct LineCount;
+
+impl OptionKind for LineCount {
+    type Value = u16;
+
+    fn shape() -> SyntaxShape {
+        SyntaxShape::Int
+    }
+
+    fn from_value(value: Value, _engine: &EngineInterface) -> Result<u16, LabeledError> {
+        let span = value.span();
+        u16::try_from(i64::from_value(value)?).map_err(|_| {
+            LabeledError::new("Invalid line count")
+                .with_label(format!("must be between 0 and {}", u16::MAX), span)
+        })
+    }
+
+    fn parse_str(value: &str) -> Result<u16, String> {
+        value
+            .parse()
+            .map_err(|_| format!("`{value}` is not a number between 0 and {}", u16::MAX))
+    }
+}
+
+pub struct FilePath;
+
+impl OptionKind for FilePath {
+    type Value = String;
+
+    fn shape() -> SyntaxShape {
+        SyntaxShape::Filepath
+    }
+
+    fn from_value(value: Value, _engine: &EngineInterface) -> Result<String, LabeledError> {
+        Ok(String::from_value(value)?)
+    }
+
+    fn parse_str(value: &str) -> Result<String, String> {
+        Ok(value.to_owned())
+    }
+}
+
+/// One of the values of a skim enum.
+pub struct Choice<T>(PhantomData<T>);
+
+impl<T: ValueEnum> OptionKind for Choice<T> {
+    type Value = T;
+
+    fn shape() -> SyntaxShape {
+        SyntaxShape::String
+    }
+
+    fn from_value(value: Value, _engine: &EngineInterface) -> Result<T, LabeledError> {
+        parse_value_enum_from_flag(value)
+    }
+
+    fn parse_str(value: &str) -> Result<T, String> {
+        parse_value_enum(value)
+    }
+}
+
+/// A list of the values of a skim enum.
+pub struct ChoiceList<T>(PhantomData<T>);
+
+impl<T: ValueEnum> OptionKind for ChoiceList<T> {
+    type Value = Vec<T>;
+
+    fn shape() -> SyntaxShape {
+        SyntaxShape::List(Box::new(SyntaxShape::String))
+    }
+
+    fn from_value(value: Value, _engine: &EngineInterface) -> Result<Vec<T>, LabeledError> {
+        value
+            .into_list()?
+            .into_iter()
+            .map(parse_value_enum_from_flag)
+            .collect()
+    }
+
+    fn parse_str(value: &str) -> Result<Vec<T>, String> {
+        split_csv_like(value)
+            .iter()
+            .map(|value| parse_value_enum(value))
+            .collect()
+    }
+}
+
+/// A regex. Escapes like `\t` and `\x00` are handled by the regex syntax itself.
+pub struct Delimiter;
+
+impl OptionKind for Delimiter {
+    type Value = Regex;
+
+    fn shape() -> SyntaxShape {
+        SyntaxShape::String
+    }
+
+    fn from_value(value: Value, _engine: &EngineInterface) -> Result<Regex, LabeledError> {
+        parse_delimiter(value.as_str()?)
+            .map_err(|err| LabeledError::new("Invalid delimiter").with_label(err, value.span()))
+    }
+
+    fn parse_str(value: &str) -> Result<Regex, String> {
+        parse_delimiter(value)
+    }
+}
+
+/// Field ranges like `2..` or `1,-1` (like in the regular `sk`), or an int for a single field.
+pub struct FieldRanges;
+
+impl OptionKind for FieldRanges {
+    type Value = Vec<String>;
+
+    fn shape() -> SyntaxShape {
+        SyntaxShape::OneOf(vec![SyntaxShape::String, SyntaxShape::Int])
+    }
+
+    fn from_value(value: Value, _engine: &EngineInterface) -> Result<Vec<String>, LabeledError> {
+        let ranges = match &value {
+            Value::Int { val, .. } => parse_field_ranges(&val.to_string()),
+            _ => parse_field_ranges(value.as_str()?),
+        };
+        ranges.map_err(|err| LabeledError::new("Invalid field range").with_label(err, value.span()))
+    }
+
+    fn parse_str(value: &str) -> Result<Vec<String>, String> {
+        parse_field_ranges(value)
+    }
+}
+
+pub struct Margin;
+
+impl OptionKind for Margin {
+    type Value = String;
+
+    fn shape() -> SyntaxShape {
+        SyntaxShape::OneOf(vec![
+            SyntaxShape::String,
+            SyntaxShape::Int,
+            SyntaxShape::Record(Default::default()),
+        ])
+    }
+
+    fn from_value(value: Value, _engine: &EngineInterface) -> Result<String, LabeledError> {
+        layout_flags::parse_margin(&value)
+    }
+
+    fn parse_str(value: &str) -> Result<String, String> {
+        check_layout_flag(layout_flags::parse_margin, value)
+    }
+}
+
+pub struct Height;
+
+impl OptionKind for Height {
+    type Value = String;
+
+    fn shape() -> SyntaxShape {
+        SyntaxShape::OneOf(vec![SyntaxShape::String, SyntaxShape::Int])
+    }
+
+    fn from_value(value: Value, _engine: &EngineInterface) -> Result<String, LabeledError> {
+        layout_flags::parse_height(&value)
+    }
+
+    fn parse_str(value: &str) -> Result<String, String> {
+        check_layout_flag(layout_flags::parse_height, value)
+    }
+}
+
+pub struct PreviewWindow;
+
+impl OptionKind for PreviewWindow {
+    type Value = PreviewLayout;
+
+    fn shape() -> SyntaxShape {
+        SyntaxShape::OneOf(vec![
+            SyntaxShape::String,
+            SyntaxShape::Record(Default::default()),
+        ])
+    }
+
+    fn from_value(value: Value, _engine: &EngineInterface) -> Result<PreviewLayout, LabeledError> {
+        layout_flags::parse_preview_window(&value)
+    }
+
+    fn parse_str(value: &str) -> Result<PreviewLayout, String> {
+        check_layout_flag(layout_flags::parse_preview_window, value)
+    }
+}
+
+/// A string in skim's syntax, or a record like in `color_config`.
+pub struct Color;
+
+impl OptionKind for Color {
+    type Value = String;
+
+    fn shape() -> SyntaxShape {
+        SyntaxShape::OneOf(vec![
+            SyntaxShape::String,
+            SyntaxShape::Record(Default::default()),
+        ])
+    }
+
+    fn from_value(value: Value, engine: &EngineInterface) -> Result<String, LabeledError> {
+        match value {
+            Value::Record { .. } => skim_colors::theme_from_record(&value, &*engine.get_config()?),
+            _ => Ok(value.coerce_into_string()?),
+        }
+    }
+
+    fn parse_str(value: &str) -> Result<String, String> {
+        Ok(value.to_owned())
+    }
+}
+
+/// A string, or a closure that generates it.
+pub struct Header;
+
+impl OptionKind for Header {
+    type Value = String;
+
+    fn shape() -> SyntaxShape {
+        SyntaxShape::OneOf(vec![
+            SyntaxShape::String,
+            SyntaxShape::Closure(Some(vec![])),
+        ])
+    }
+
+    fn from_value(value: Value, engine: &EngineInterface) -> Result<String, LabeledError> {
+        match value {
+            Value::Closure {
+                val, internal_span, ..
+            } => {
+                let header =
+                    engine.eval_closure(&(*val).into_spanned(internal_span), vec![], None)?;
+                Ok(header.to_expanded_string(", ", &*engine.get_config()?))
+            }
+            _ => Ok(value.coerce_into_string()?),
+        }
+    }
+
+    fn parse_str(value: &str) -> Result<String, String> {
+        Ok(value.to_owned())
+    }
+}
+
+pub struct Bind;
+
+impl OptionKind for Bind {
+    type Value = Bindings;
+
+    fn shape() -> SyntaxShape {
+        SyntaxShape::Record(Default::default())
+    }
+
+    fn from_value(value: Value, engine: &EngineInterface) -> Result<Bindings, LabeledError> {
+        Bindings::from_record(value.into_record()?, engine)
+    }
+
+    fn parse_str(value: &str) -> Result<Bindings, String> {
+        Ok(Bindings::from_options_str(value))
+    }
+}
+
+pub struct Predicate;
+
+impl OptionKind for Predicate {
+    type Value = Spanned<Closure>;
+
+    fn shape() -> SyntaxShape {
+        SyntaxShape::Closure(Some(vec![]))
+    }
+
+    fn from_value(
+        value: Value,
+        _engine: &EngineInterface,
+    ) -> Result<Spanned<Closure>, LabeledError> {
+        Ok(Spanned::from_value(value)?)
+    }
+
+    fn parse_str(_value: &str) -> Result<Spanned<Closure>, String> {
+        Err(NOT_IN_ENV.to_owned())
+    }
+}
+
+/// An option that would make every `sk` call behave differently, so it must be passed explicitly
+/// (or in `$env.SKIM_NU_DEFAULTS`).
+pub struct NotInEnv<K>(PhantomData<K>);
+
+impl<K: OptionKind> OptionKind for NotInEnv<K> {
+    type Value = K::Value;
+
+    fn shape() -> SyntaxShape {
+        K::shape()
+    }
+
+    fn from_value(value: Value, engine: &EngineInterface) -> Result<K::Value, LabeledError> {
+        K::from_value(value, engine)
+    }
+
+    fn parse_str(_value: &str) -> Result<K::Value, String> {
+        Err(NOT_IN_ENV.to_owned())
+    }
+}
+
+fn parse_number(value: &str) -> Result<usize, String> {
+    value
+        .parse()
+        .map_err(|_| format!("`{value}` is not a non-negative number"))
+}
+
+fn parse_value_enum<T: ValueEnum>(value: &str) -> Result<T, String> {
+    T::from_str(value, false).map_err(|_| {
+        format!(
+            "`{value}` is not one of {}",
+            value_enum_possibilities_string::<T>()
+        )
+    })
+}
+
+fn parse_delimiter(value: &str) -> Result<Regex, String> {
+    Regex::new(value).map_err(|err| err.to_string())
+}
+
+fn parse_field_ranges(value: &str) -> Result<Vec<String>, String> {
+    value
+        .split(',')
+        .map(|range| {
+            if FieldRange::from_str(range).is_none() {
+                return Err(format!(
+                    "`{range}` is not a field range - expected something like 2, -1, 2.. or 1..3"
+                ));
+            }
+            // Skim would silently use the first field for bounds that don't fit, and never match
+            // field 0
+            for bound in range.split("..").filter(|bound| !bound.is_empty()) {
+                match bound.parse::<i32>() {
+                    Ok(0) => {
+                        return Err(format!("`{range}` refers to field 0 - fields start at 1"));
+                    }
+                    Ok(_) => {}
+                    Err(_) => return Err(format!("`{range}` refers to a field that is too far")),
+                }
+            }
+            Ok(range.to_owned())
+        })
+        .collect()
+}
+
+/// Runs the same validation as when the flag is passed explicitly.
+fn check_layout_flag<T>(
+    parse: impl FnOnce(&Value) -> Result<T, LabeledError>,
+    value: &str,
+) -> Result<T, String> {
+    parse(&Value::string(value, Span::unknown())).map_err(|err| match err.labels.first() {
+        Some(label) => format!("{}: {}", err.msg, label.text),
+        None => err.msg,
+    })
+}
+
+fn split_csv_like(s: &str) -> Vec<String> {
+    s.split(&[',', ' '] as &[_])
+        .filter(|t| !t.is_empty())
+        .map(|t| t.to_string())
+        .collect()
+}
+
+fn with_value_enum_possible_values<T: ValueEnum>(mut dlg: impl FnMut(&str)) {
+    for variant in T::value_variants() {
+        let Some(possible_value) = variant.to_possible_value() else {
+            continue;
+        };
+        for value in possible_value.get_name_and_aliases() {
+            dlg(value);
+        }
+    }
+}
+
+pub fn value_enum_possibilities_string<T: ValueEnum>() -> String {
+    let mut result = String::new();
+    with_value_enum_possible_values::<T>(|value| {
+        if !result.is_empty() {
+            result.push('|');
+        }
+        result.push_str(value);
+    });
+    result
+}
+
+#[allow(clippy::result_large_err)]
+fn parse_value_enum_from_flag<T: ValueEnum>(flag: Value) -> Result<T, LabeledError> {
+    let str_value = flag.as_str()?;
+    T::from_str(str_value, false).map_err(|_| {
+        ShellError::InvalidValue {
+            valid: format!("[{}]", value_enum_possibilities_string::<T>()),
+            actual: str_value.to_owned(),
+            span: flag.span(),
+        }
+        .into()
+    })
+}
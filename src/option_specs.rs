@@ -0,0 +1,264 @@
+//! The names of the flags of `sk`, shared by its signature and by the parser of
+//! `SKIM_DEFAULT_OPTIONS` - so that the short flags and the flags that take values are the same in
+//! both.
+//!
+//! The flags of the options that are passed on to skim are declared with the options themselves, in
+//! `cli_arguments`.
+
+use crate::cli_arguments::SKIM_OPTION_SPECS;
+
+pub struct OptionSpec {
+    pub long: &'static str,
+    pub short: Option<char>,
+    /// Whether the flag takes a value, as opposed to being a switch.
+    pub takes_value: bool,
+}
+
+const fn switch(long: &'static str, short: Option<char>) -> OptionSpec {
+    OptionSpec {
+        long,
+        short,
+        takes_value: false,
+    }
+}
+
+const fn named(long: &'static str, short: Option<char>) -> OptionSpec {
+    OptionSpec {
+        long,
+        short,
+        takes_value: true,
+    }
+}
+
+/// The flags that `run_sk` handles itself.
+pub const OPTION_SPECS: &[OptionSpec] = &[
+    named("format", Some('f')),
+    switch("table", Some('t')),
+    named("nth", Some('n')),
+    named("preview", Some('p')),
+    named("preview-cache-size", None),
+    switch("keep-order", None),
+    switch("index", None),
+    switch("with-meta", None),
+    named("parse", None),
+    named("cmd", Some('c')),
+];
+
+fn all() -> impl Iterator<Item = &'static OptionSpec> {
+    OPTION_SPECS.iter().chain(SKIM_OPTION_SPECS)
+}
+
+pub fn by_long(long: &str) -> Option<&'static OptionSpec> {
+    all().find(|spec| spec.long == long)
+}
+
+pub fn by_short(short: char) -> Option<&'static OptionSpec> {
+    all().find(|spec| spec.short == Some(short))
+}
+
+/// The short flag to register in the signature.
+pub fn short(long: &str) -> Option<char> {
+    by_long(long).and_then(|spec| spec.short)
+}
+
+/// An option from a command line string, with the short flags resolved to their long names.
+pub struct ParsedOption {
+    /// How the option was written, for reporting.
+    pub option: String,
+    pub spec: Result<&'static OptionSpec, String>,
+    pub value: Option<String>,
+}
+
+/// Splits shell-like tokens to options, resolving bundled short flags (like `-mq foo` or
+/// `-mqfoo`) and taking values for the flags that need them.
+///
+/// Tokens that are not options are returned with an `Err` spec, so that they can be reported.
+pub fn parse_options(tokens: impl IntoIterator<Item = String>) -> Vec<ParsedOption> {
+    let mut tokens = tokens.into_iter();
+    let mut options = Vec::new();
+    while let Some(token) = tokens.next() {
+        if token == "--" {
+            options.extend(tokens.by_ref().map(|token| ParsedOption {
+                option: token,
+                spec: Err("comes after `--`".to_owned()),
+                value: None,
+            }));
+            break;
+        }
+        if let Some(rest) = token.strip_prefix("--") {
+            let (long, inline_value) = match rest.split_once('=') {
+                Some((long, value)) => (long, Some(value.to_owned())),
+                None => (rest, None),
+            };
+            let option = format!("--{long}");
+            let Some(spec) = by_long(long) else {
+                options.push(ParsedOption {
+                    option,
+                    spec: Err("unknown option".to_owned()),
+                    value: inline_value,
+                });
+                continue;
+            };
+            let value = if spec.takes_value {
+                inline_value.or_else(|| tokens.next())
+            } else {
+                inline_value
+            };
+            options.push(ParsedOption {
+                option,
+                spec: Ok(spec),
+                value,
+            });
+        } else if let Some(bundle) = token.strip_prefix('-')
+            && !bundle.is_empty()
+        {
+            let mut chars = bundle.chars();
+            while let Some(short) = chars.next() {
+                let option = format!("-{short}");
+                let Some(spec) = by_short(short) else {
+                    options.push(ParsedOption {
+                        option,
+                        spec: Err("unknown option".to_owned()),
+                        value: None,
+                    });
+                    continue;
+                };
+                if spec.takes_value {
+                    // The rest of the bundle is the value - or the next token if there is no rest
+                    let rest = chars.as_str();
+                    let value = if rest.is_empty() {
+                        tokens.next()
+                    } else {
+                        Some(rest.to_owned())
+                    };
+                    options.push(ParsedOption {
+                        option,
+                        spec: Ok(spec),
+                        value,
+                    });
+                    break;
+                }
+                options.push(ParsedOption {
+                    option,
+                    spec: Ok(spec),
+                    value: None,
+                });
+            }
+        } else {
+            options.push(ParsedOption {
+                option: token,
+                spec: Err("not an option".to_owned()),
+                value: None,
+            });
+        }
+    }
+    options
+}
+
+#[cfg(test)]
+mod tests {
+    use nu_protocol::Signature;
+
+    use super::*;
+
+    fn parse(options: &[&str]) -> Vec<(String, Option<&'static str>, Option<String>)> {
+        parse_options(options.iter().map(|option| option.to_string()))
+            .into_iter()
+            .map(|parsed| {
+                (
+                    parsed.option,
+                    parsed.spec.ok().map(|spec| spec.long),
+                    parsed.value,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn specs_match_the_signature() {
+        let signature = crate::add_sk_flags(Signature::build("sk"));
+        let flags = signature
+            .named
+            .iter()
+            .filter(|flag| flag.long != "help")
+            .collect::<Vec<_>>();
+        for flag in &flags {
+            let spec =
+                by_long(&flag.long).unwrap_or_else(|| panic!("--{} has no option spec", flag.long));
+            assert_eq!(flag.short, spec.short, "short flag of --{}", flag.long);
+            assert_eq!(
+                flag.arg.is_some(),
+                spec.takes_value,
+                "whether --{} takes a value",
+                flag.long
+            );
+        }
+        for spec in all() {
+            assert!(
+                flags.iter().any(|flag| flag.long == spec.long),
+                "--{} is not in the signature",
+                spec.long
+            );
+        }
+    }
+
+    #[test]
+    fn short_flags_are_resolved() {
+        assert_eq!(
+            parse(&["-m", "-q", "foo", "-c", "ls", "-1"]),
+            [
+                ("-m".to_owned(), Some("multi"), None),
+                ("-q".to_owned(), Some("query"), Some("foo".to_owned())),
+                ("-c".to_owned(), Some("cmd"), Some("ls".to_owned())),
+                ("-1".to_owned(), Some("select-1"), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn short_flags_can_be_bundled() {
+        assert_eq!(
+            parse(&["-mei0", "-mqfoo", "-iq", "bar baz", "-mx"]),
+            [
+                ("-m".to_owned(), Some("multi"), None),
+                ("-e".to_owned(), Some("exact"), None),
+                ("-i".to_owned(), Some("interactive"), None),
+                ("-0".to_owned(), Some("exit-0"), None),
+                ("-m".to_owned(), Some("multi"), None),
+                ("-q".to_owned(), Some("query"), Some("foo".to_owned())),
+                ("-i".to_owned(), Some("interactive"), None),
+                ("-q".to_owned(), Some("query"), Some("bar baz".to_owned())),
+                ("-m".to_owned(), Some("multi"), None),
+                ("-x".to_owned(), None, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn long_flags_take_values_only_if_they_need_them() {
+        assert_eq!(
+            parse(&[
+                "--multi",
+                "--prompt",
+                "> ",
+                "--height=40%",
+                "--nope",
+                "stray",
+                "--",
+                "-m"
+            ]),
+            [
+                ("--multi".to_owned(), Some("multi"), None),
+                ("--prompt".to_owned(), Some("prompt"), Some("> ".to_owned())),
+                (
+                    "--height".to_owned(),
+                    Some("height"),
+                    Some("40%".to_owned())
+                ),
+                ("--nope".to_owned(), None, None),
+                ("stray".to_owned(), None, None),
+                ("-m".to_owned(), None, None),
+            ]
+        );
+    }
+}
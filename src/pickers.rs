@@ -0,0 +1,603 @@
+//! Ready-made pickers, which run `sk` with defaults that suit the things they pick from.
+
+use std::collections::{HashSet, VecDeque};
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use nu_plugin::{EngineInterface, EvaluatedCall, PluginCommand};
+use nu_protocol::{
+    Category, LabeledError, ListStream, PipelineData, Record, Signals, Signature, Span, Spanned,
+    SyntaxShape, Type, Value, record,
+};
+
+use crate::command_context::{CommandContext, MapperFlag};
+use crate::{PickerDefaults, SkimPlugin, UnsupportedFlags, add_sk_flags, run_sk};
+
+/// How much of a file `sk files` shows in the preview.
+const FILE_PREVIEW_BYTES: u64 = 64 * 1024;
+
+pub struct SkFiles;
+
+impl PluginCommand for SkFiles {
+    type Plugin = SkimPlugin;
+
+    fn name(&self) -> &str {
+        "sk files"
+    }
+
+    fn signature(&self) -> Signature {
+        add_sk_flags(
+            Signature::build(self.name())
+                .input_output_type(Type::Nothing, Type::Any)
+                .category(Category::FileSystem)
+                .optional(
+                    "path",
+                    SyntaxShape::Directory,
+                    "The directory to pick files from (default: the current directory)",
+                )
+                .switch("hidden", "Include hidden files and directories", None),
+        )
+    }
+
+    fn description(&self) -> &str {
+        "Select files from a directory tree using skim"
+    }
+
+    fn run(
+        &self,
+        _plugin: &SkimPlugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        _input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let span = call.head;
+        let path = call.opt::<Spanned<String>>(0)?;
+        let root = Path::new(&engine.get_current_dir()?).join(
+            path.as_ref()
+                .map_or(Path::new("."), |path| Path::new(&path.item)),
+        );
+        if !root.is_dir() {
+            return Err(LabeledError::new("Not a directory").with_label(
+                format!("{} is not a directory", root.display()),
+                path.map_or(span, |path| path.span),
+            ));
+        }
+        let walker = FileWalker::new(
+            root,
+            path.map(|path| PathBuf::from(path.item))
+                .unwrap_or_default(),
+            call.has_flag("hidden")?,
+            span,
+        );
+        run_sk(
+            engine,
+            call,
+            PipelineData::ListStream(ListStream::new(walker, span, Signals::EMPTY), None),
+            PickerDefaults {
+                format: Some(MapperFlag::Builtin(|_, value| field(value, "name"))),
+                preview: Some(MapperFlag::Builtin(file_preview)),
+                ..Default::default()
+            },
+        )
+    }
+}
+
+/// Walks the directory tree breadth first, so that the shallower files come first.
+struct FileWalker {
+    root: PathBuf,
+    /// What the paths of the entries are displayed relative to - the path the user gave.
+    prefix: PathBuf,
+    hidden: bool,
+    span: Span,
+    /// Directories to read, relative to the root.
+    dirs: VecDeque<PathBuf>,
+    entries: VecDeque<Value>,
+}
+
+impl FileWalker {
+    fn new(root: PathBuf, prefix: PathBuf, hidden: bool, span: Span) -> Self {
+        Self {
+            root,
+            prefix,
+            hidden,
+            span,
+            dirs: VecDeque::from([PathBuf::new()]),
+            entries: VecDeque::new(),
+        }
+    }
+
+    /// Directories that cannot be read are skipped, like `ls **/*` skips them.
+    fn read_dir(&mut self, dir: PathBuf) {
+        let Ok(read_dir) = std::fs::read_dir(self.root.join(&dir)) else {
+            return;
+        };
+        let mut dir_entries = read_dir
+            .filter_map(Result::ok)
+            .filter(|entry| self.hidden || !entry.file_name().to_string_lossy().starts_with('.'))
+            .collect::<Vec<_>>();
+        dir_entries.sort_by_key(|entry| entry.file_name());
+        for entry in dir_entries {
+            let Ok(metadata) = entry.path().symlink_metadata() else {
+                continue;
+            };
+            let relative = dir.join(entry.file_name());
+            let file_type = if metadata.is_dir() {
+                // Symlinks are not followed, so there can be no cycles
+                self.dirs.push_back(relative.clone());
+                "dir"
+            } else if metadata.is_symlink() {
+                "symlink"
+            } else {
+                "file"
+            };
+            let span = self.span;
+            self.entries.push_back(Value::record(
+                record! {
+                    "name" => Value::string(self.prefix.join(relative).to_string_lossy(), span),
+                    "type" => Value::string(file_type, span),
+                    "size" => Value::filesize(metadata.len() as i64, span),
+                    "modified" => match metadata.modified() {
+                        Ok(modified) => Value::date(
+                            chrono::DateTime::<chrono::Local>::from(modified).fixed_offset(),
+                            span,
+                        ),
+                        Err(_) => Value::nothing(span),
+                    },
+                },
+                span,
+            ));
+        }
+    }
+}
+
+impl Iterator for FileWalker {
+    type Item = Value;
+
+    fn next(&mut self) -> Option<Value> {
+        loop {
+            if let Some(entry) = self.entries.pop_front() {
+                return Some(entry);
+            }
+            let dir = self.dirs.pop_front()?;
+            self.read_dir(dir);
+        }
+    }
+}
+
+/// The beginning of the file, or the contents of the directory.
+fn file_preview(context: &CommandContext, value: &Value) -> Value {
+    let span = value.span();
+    let Ok(name) = field(value, "name").coerce_into_string() else {
+        return value.clone();
+    };
+    let path = match context.engine.get_current_dir() {
+        Ok(cwd) => Path::new(&cwd).join(name),
+        Err(err) => return Value::error(err, span),
+    };
+    let text = if path.is_dir() {
+        std::fs::read_dir(&path).map(|read_dir| {
+            let mut names = read_dir
+                .filter_map(Result::ok)
+                .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                .collect::<Vec<_>>();
+            names.sort();
+            names.join("\n")
+        })
+    } else {
+        File::open(&path).and_then(|file| {
+            let mut content = Vec::new();
+            file.take(FILE_PREVIEW_BYTES).read_to_end(&mut content)?;
+            Ok(if content.contains(&0) {
+                "(binary file)".to_owned()
+            } else {
+                String::from_utf8_lossy(&content).into_owned()
+            })
+        })
+    };
+    match text {
+        Ok(text) => Value::string(text, span),
+        Err(err) => Value::string(format!("{}: {err}", path.display()), span),
+    }
+}
+
+pub struct SkHistory;
+
+impl PluginCommand for SkHistory {
+    type Plugin = SkimPlugin;
+
+    fn name(&self) -> &str {
+        "sk history"
+    }
+
+    fn signature(&self) -> Signature {
+        add_sk_flags(
+            Signature::build(self.name())
+                .input_output_type(Type::Nothing, Type::Any)
+                .category(Category::History),
+        )
+    }
+
+    fn description(&self) -> &str {
+        "Select a command from Nushell's history using skim"
+    }
+
+    fn run(
+        &self,
+        _plugin: &SkimPlugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        _input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let span = call.head;
+        let history_decl = engine
+            .find_decl("history")?
+            .ok_or_else(|| LabeledError::new("The `history` command is not available"))?;
+        let history = engine.call_decl(
+            history_decl,
+            EvaluatedCall::new(span),
+            PipelineData::empty(),
+            true,
+            false,
+        )?;
+        let entries = recent_unique_commands(history.into_iter().collect());
+        run_sk(
+            engine,
+            call,
+            PipelineData::Value(Value::list(entries, span), None),
+            PickerDefaults {
+                format: Some(MapperFlag::Builtin(|_, value| field(value, "command"))),
+                preview: Some(MapperFlag::Builtin(history_preview)),
+                ..Default::default()
+            },
+        )
+    }
+}
+
+/// The most recent commands come first, and each command appears only once.
+fn recent_unique_commands(history: Vec<Value>) -> Vec<Value> {
+    let mut seen = HashSet::new();
+    history
+        .into_iter()
+        .rev()
+        .filter(|entry| {
+            field(entry, "command")
+                .coerce_into_string()
+                .map_or(true, |command| seen.insert(command))
+        })
+        .collect()
+}
+
+/// The columns of a history entry that `sk history` shows in the preview. A plain-text history has
+/// none of them.
+const HISTORY_PREVIEW_COLUMNS: [&str; 4] = ["start_timestamp", "cwd", "duration", "exit_status"];
+
+/// Where and when the command ran, and how it went.
+fn history_preview(_context: &CommandContext, value: &Value) -> Value {
+    let span = value.span();
+    let Value::Record { val, .. } = value else {
+        return Value::nothing(span);
+    };
+    let details = val
+        .iter()
+        .filter(|(column, _)| HISTORY_PREVIEW_COLUMNS.contains(&column.as_str()))
+        .map(|(column, value)| (column.clone(), value.clone()))
+        .collect::<Record>();
+    if details.is_empty() {
+        Value::nothing(span)
+    } else {
+        Value::record(details, span)
+    }
+}
+
+pub struct SkEnv;
+
+impl PluginCommand for SkEnv {
+    type Plugin = SkimPlugin;
+
+    fn name(&self) -> &str {
+        "sk env"
+    }
+
+    fn signature(&self) -> Signature {
+        add_sk_flags(
+            Signature::build(self.name())
+                .input_output_type(Type::Nothing, Type::Any)
+                .category(Category::Env),
+        )
+    }
+
+    fn description(&self) -> &str {
+        "Select an environment variable using skim"
+    }
+
+    fn run(
+        &self,
+        _plugin: &SkimPlugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        _input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let span = call.head;
+        let mut env_vars = engine.get_env_vars()?.into_iter().collect::<Vec<_>>();
+        env_vars.sort_by(|(a, _), (b, _)| a.cmp(b));
+        let entries = env_vars
+            .into_iter()
+            .map(|(name, value)| {
+                Value::record(
+                    record! {
+                        "name" => Value::string(name, span),
+                        "value" => value,
+                    },
+                    span,
+                )
+            })
+            .collect();
+        run_sk(
+            engine,
+            call,
+            PipelineData::Value(Value::list(entries, span), None),
+            PickerDefaults {
+                format: Some(MapperFlag::Builtin(|context, entry| {
+                    let name = field(entry, "name").to_expanded_string("", &context.nu_config);
+                    let value = field(entry, "value").to_expanded_string(", ", &context.nu_config);
+                    Value::string(format!("{name}={value}"), entry.span())
+                })),
+                preview: Some(MapperFlag::Builtin(|_, value| field(value, "value"))),
+                ..Default::default()
+            },
+        )
+    }
+}
+
+/// The field of a record, or the value itself if it's not a record with that field.
+fn field(value: &Value, name: &str) -> Value {
+    value.get_data_by_key(name).unwrap_or_else(|| value.clone())
+}
+
+/// How many values of each column `sk columns` shows in the preview.
+const COLUMN_SAMPLE_SIZE: usize = 20;
+
+pub struct SkColumns;
+
+impl PluginCommand for SkColumns {
+    type Plugin = SkimPlugin;
+
+    fn name(&self) -> &str {
+        "sk columns"
+    }
+
+    fn signature(&self) -> Signature {
+        add_sk_flags(
+            Signature::build(self.name())
+                .input_output_type(Type::table(), Type::table())
+                .category(Category::Filters),
+        )
+    }
+
+    fn description(&self) -> &str {
+        "Select columns of the input table using skim"
+    }
+
+    fn extra_description(&self) -> &str {
+        "The table is returned with only the selected columns, in the order they were selected."
+    }
+
+    fn run(
+        &self,
+        _plugin: &SkimPlugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        mut input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let span = call.head;
+        let pipeline_metadata = input.take_metadata();
+        // The input is buffered, since it's only narrowed after the columns are selected
+        let rows = input.into_iter().collect::<Vec<_>>();
+        let columns = column_samples(&rows)?;
+        let entries = columns
+            .into_iter()
+            .map(|(name, samples)| {
+                Value::record(
+                    record! {
+                        "name" => Value::string(name, span),
+                        "samples" => Value::list(samples, span),
+                    },
+                    span,
+                )
+            })
+            .collect();
+        let selected = run_sk(
+            engine,
+            call,
+            PipelineData::Value(Value::list(entries, span), None),
+            PickerDefaults {
+                format: Some(MapperFlag::Builtin(|_, value| field(value, "name"))),
+                preview: Some(MapperFlag::Builtin(|_, value| field(value, "samples"))),
+                multi: true,
+                unsupported: Some(UnsupportedFlags {
+                    command: "sk columns",
+                    flags: &["index", "with-meta", "expect", "parse", "cmd"],
+                    reason: "the selected columns are used to narrow the table",
+                }),
+            },
+        )?;
+        let selected = selected
+            .into_iter()
+            .filter_map(|entry| field(&entry, "name").coerce_into_string().ok())
+            .collect::<Vec<_>>();
+        if selected.is_empty() {
+            return Ok(PipelineData::empty());
+        }
+        let narrowed = rows.into_iter().map(move |row| narrow_row(row, &selected));
+        Ok(PipelineData::ListStream(
+            ListStream::new(narrowed, span, Signals::EMPTY),
+            pipeline_metadata,
+        ))
+    }
+}
+
+/// The columns of the table, in the order they first appear, with the first values of each.
+#[allow(clippy::result_large_err)]
+fn column_samples(rows: &[Value]) -> Result<Vec<(String, Vec<Value>)>, LabeledError> {
+    let mut columns = Vec::<(String, Vec<Value>)>::new();
+    for row in rows {
+        let Value::Record { val, .. } = row else {
+            return Err(LabeledError::new("Expected a table")
+                .with_label(format!("got a row of type {}", row.get_type()), row.span()));
+        };
+        for (name, value) in val.iter() {
+            let samples = match columns.iter_mut().find(|(column, _)| column == name) {
+                Some((_, samples)) => samples,
+                None => {
+                    columns.push((name.clone(), Vec::new()));
+                    &mut columns.last_mut().expect("just pushed").1
+                }
+            };
+            if samples.len() < COLUMN_SAMPLE_SIZE {
+                samples.push(value.clone());
+            }
+        }
+    }
+    Ok(columns)
+}
+
+/// The row with only the selected columns, in the order they were selected. Rows that lack one of
+/// them get `null` there.
+fn narrow_row(row: Value, selected: &[String]) -> Value {
+    let row_span = row.span();
+    let Value::Record { val, .. } = row else {
+        unreachable!("all rows were checked to be records")
+    };
+    let mut record = val.into_owned();
+    Value::record(
+        selected
+            .iter()
+            .map(|name| {
+                let value = record
+                    .remove(name)
+                    .unwrap_or_else(|| Value::nothing(row_span));
+                (name.clone(), value)
+            })
+            .collect(),
+        row_span,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn files_are_walked_breadth_first() {
+        let root = std::env::temp_dir().join(format!("nu_plugin_skim_walk_{}", std::process::id()));
+        for dir in ["a/deeper", ".hidden"] {
+            std::fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        for file in ["b.txt", "a/c.txt", "a/deeper/d.txt", ".hidden/e.txt", ".f"] {
+            std::fs::write(root.join(file), "").unwrap();
+        }
+        let names = |hidden: bool| {
+            FileWalker::new(
+                root.clone(),
+                PathBuf::from("dir"),
+                hidden,
+                Span::test_data(),
+            )
+            .map(|entry| {
+                let name = field(&entry, "name").coerce_into_string().unwrap();
+                let file_type = field(&entry, "type").coerce_into_string().unwrap();
+                format!("{name} ({file_type})")
+            })
+            .collect::<Vec<_>>()
+        };
+        let visible = names(false);
+        let all = names(true);
+        std::fs::remove_dir_all(&root).unwrap();
+        assert_eq!(
+            visible,
+            [
+                "dir/a (dir)",
+                "dir/b.txt (file)",
+                "dir/a/c.txt (file)",
+                "dir/a/deeper (dir)",
+                "dir/a/deeper/d.txt (file)",
+            ]
+        );
+        assert_eq!(
+            all,
+            [
+                "dir/.f (file)",
+                "dir/.hidden (dir)",
+                "dir/a (dir)",
+                "dir/b.txt (file)",
+                "dir/.hidden/e.txt (file)",
+                "dir/a/c.txt (file)",
+                "dir/a/deeper (dir)",
+                "dir/a/deeper/d.txt (file)",
+            ]
+        );
+    }
+
+    #[test]
+    fn columns_are_sampled_in_order_of_appearance() {
+        let rows = (0..30)
+            .map(|index| {
+                let mut row = record! { "pid" => Value::test_int(index) };
+                if index == 25 {
+                    row.push("late", Value::test_bool(true));
+                }
+                Value::test_record(row)
+            })
+            .collect::<Vec<_>>();
+        let columns = column_samples(&rows).unwrap();
+        let names = columns
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["pid", "late"]);
+        assert_eq!(columns[0].1.len(), COLUMN_SAMPLE_SIZE);
+        assert_eq!(columns[0].1[3], Value::test_int(3));
+        assert_eq!(columns[1].1, [Value::test_bool(true)]);
+
+        assert!(column_samples(&[Value::test_int(1)]).is_err());
+    }
+
+    #[test]
+    fn rows_are_narrowed_to_the_selected_columns_in_order() {
+        let row = Value::test_record(record! {
+            "name" => Value::test_string("nu"),
+            "pid" => Value::test_int(1),
+            "cpu" => Value::test_float(0.5),
+        });
+        let selected = ["cpu", "name", "missing"].map(str::to_owned);
+        assert_eq!(
+            narrow_row(row, &selected),
+            Value::test_record(record! {
+                "cpu" => Value::test_float(0.5),
+                "name" => Value::test_string("nu"),
+                "missing" => Value::test_nothing(),
+            })
+        );
+    }
+
+    #[test]
+    fn history_has_the_most_recent_commands_once() {
+        let entry = |command: &str, exit_status: i64| {
+            Value::test_record(record! {
+                "command" => Value::test_string(command),
+                "exit_status" => Value::test_int(exit_status),
+            })
+        };
+        let entries = recent_unique_commands(vec![
+            entry("ls", 1),
+            entry("cd src", 0),
+            entry("ls", 0),
+            entry("git status", 0),
+        ]);
+        assert_eq!(
+            entries,
+            [entry("git status", 0), entry("ls", 0), entry("cd src", 0)]
+        );
+    }
+}
@@ -0,0 +1,220 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+use skim::prelude::{Receiver, Sender, unbounded};
+
+/// How long Skim's UI thread waits for a preview before showing the placeholder instead, so that
+/// fast previews don't flicker.
+const WAIT_FOR_PREVIEW: Duration = Duration::from_millis(50);
+
+pub const PREVIEW_PLACEHOLDER: &str = "loading…";
+
+struct PreviewRequest<K, V> {
+    key: K,
+    generation: usize,
+    render: Box<dyn FnOnce() -> V + Send>,
+}
+
+/// Renders the previews on a background thread, and keeps the most recently used ones.
+///
+/// The key is everything the preview depends on.
+pub struct PreviewCache<K, V> {
+    shared: Arc<Shared<K, V>>,
+    requests: Sender<PreviewRequest<K, V>>,
+}
+
+type OnReady = Arc<dyn Fn() + Send + Sync>;
+
+struct Shared<K, V> {
+    state: Mutex<State<K, V>>,
+    rendered: Condvar,
+}
+
+struct State<K, V> {
+    capacity: usize,
+    /// Bumped when the items are replaced, so that previews of the old items are discarded.
+    generation: usize,
+    /// The least recently used entry is first.
+    entries: VecDeque<(K, V)>,
+    /// A preview that Skim was given the placeholder for, and needs to be told when it's ready.
+    placeholder_shown_for: Option<K>,
+    /// Makes Skim ask for the preview again.
+    on_ready: Option<OnReady>,
+}
+
+impl<K: PartialEq, V: Clone> State<K, V> {
+    fn get(&mut self, key: &K) -> Option<V> {
+        let position = self
+            .entries
+            .iter()
+            .position(|(entry_key, _)| entry_key == key)?;
+        let entry = self.entries.remove(position)?;
+        let preview = entry.1.clone();
+        self.entries.push_back(entry);
+        Some(preview)
+    }
+
+    fn insert(&mut self, key: K, preview: V) {
+        self.entries.retain(|(entry_key, _)| *entry_key != key);
+        self.entries.push_back((key, preview));
+        while self.capacity < self.entries.len() {
+            self.entries.pop_front();
+        }
+    }
+}
+
+impl<K, V> PreviewCache<K, V>
+where
+    K: PartialEq + Clone + Send + 'static,
+    V: Clone + Send + 'static,
+{
+    /// The capacity is the number of previews to keep. It is at least 1, since the preview that
+    /// is being waited for must be kept until Skim asks for it again.
+    pub fn new(capacity: usize) -> Self {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                capacity: capacity.max(1),
+                generation: 0,
+                entries: VecDeque::new(),
+                placeholder_shown_for: None,
+                on_ready: None,
+            }),
+            rendered: Condvar::new(),
+        });
+        let (requests, rx) = unbounded();
+        let worker_shared = shared.clone();
+        std::thread::spawn(move || render_requests(&worker_shared, rx));
+        Self { shared, requests }
+    }
+
+    /// Returns `None` if the preview is not ready yet, in which case it'll be rendered in the
+    /// background and Skim will be made to ask for it again.
+    pub fn get_or_render(&self, key: K, render: impl FnOnce() -> V + Send + 'static) -> Option<V> {
+        let mut state = self.shared.state.lock().ok()?;
+        if let Some(preview) = state.get(&key) {
+            return Some(preview);
+        }
+        let request = PreviewRequest {
+            key: key.clone(),
+            generation: state.generation,
+            render: Box::new(render),
+        };
+        self.requests.send(request).ok()?;
+        let (mut state, _) = self
+            .shared
+            .rendered
+            .wait_timeout_while(state, WAIT_FOR_PREVIEW, |state| {
+                !state.entries.iter().any(|(entry_key, _)| *entry_key == key)
+            })
+            .ok()?;
+        let preview = state.get(&key);
+        if preview.is_none() {
+            state.placeholder_shown_for = Some(key);
+        }
+        preview
+    }
+
+    /// Called when a preview that Skim was given the placeholder for is ready. Until this is set,
+    /// such previews are shown the next time Skim asks for them.
+    pub fn on_ready(&self, on_ready: impl Fn() + Send + Sync + 'static) {
+        if let Ok(mut state) = self.shared.state.lock() {
+            state.on_ready = Some(Arc::new(on_ready));
+        }
+    }
+
+    /// Discard all the previews, because the items they were rendered for were replaced.
+    pub fn clear(&self) {
+        if let Ok(mut state) = self.shared.state.lock() {
+            state.generation += 1;
+            state.entries.clear();
+            state.placeholder_shown_for = None;
+        }
+    }
+}
+
+fn render_requests<K: PartialEq, V: Clone>(
+    shared: &Shared<K, V>,
+    rx: Receiver<PreviewRequest<K, V>>,
+) {
+    while let Ok(mut request) = rx.recv() {
+        // Only the latest request matters - the ones before it are for items the cursor already
+        // moved away from.
+        while let Ok(Some(newer)) = rx.try_recv() {
+            request = newer;
+        }
+        let preview = (request.render)();
+        let Ok(mut state) = shared.state.lock() else {
+            return;
+        };
+        if state.generation != request.generation {
+            continue;
+        }
+        let on_ready = if state.placeholder_shown_for.as_ref() == Some(&request.key) {
+            state.placeholder_shown_for = None;
+            state.on_ready.clone()
+        } else {
+            None
+        };
+        state.insert(request.key, preview);
+        drop(state);
+        shared.rendered.notify_all();
+        if let Some(on_ready) = on_ready {
+            on_ready();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn least_recently_used_previews_are_evicted() {
+        let cache = PreviewCache::new(2);
+        assert_eq!(
+            cache.get_or_render((0, 10), || "a".to_owned()).as_deref(),
+            Some("a")
+        );
+        assert_eq!(
+            cache.get_or_render((1, 10), || "b".to_owned()).as_deref(),
+            Some("b")
+        );
+        // Using the first one makes the second one the least recently used
+        assert_eq!(
+            cache.get_or_render((0, 10), || unreachable!()).as_deref(),
+            Some("a")
+        );
+        assert_eq!(
+            cache.get_or_render((2, 10), || "c".to_owned()).as_deref(),
+            Some("c")
+        );
+        assert_eq!(
+            cache.get_or_render((0, 10), || unreachable!()).as_deref(),
+            Some("a")
+        );
+        assert_eq!(
+            cache.get_or_render((1, 10), || "b2".to_owned()).as_deref(),
+            Some("b2")
+        );
+    }
+
+    #[test]
+    fn slow_previews_are_kept_for_later() {
+        let cache = PreviewCache::new(10);
+        let slow = cache.get_or_render((0, 10), || {
+            std::thread::sleep(WAIT_FOR_PREVIEW * 4);
+            "slow".to_owned()
+        });
+        assert_eq!(slow, None);
+        let (ready_tx, ready_rx) = unbounded();
+        cache.on_ready(move || {
+            let _ = ready_tx.send(());
+        });
+        assert!(ready_rx.recv_timeout(WAIT_FOR_PREVIEW * 8).is_ok());
+        assert_eq!(
+            cache.get_or_render((0, 10), || unreachable!()).as_deref(),
+            Some("slow")
+        );
+    }
+}
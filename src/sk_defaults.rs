@@ -0,0 +1,77 @@
+use nu_plugin::{EngineInterface, EvaluatedCall, PluginCommand};
+use nu_protocol::{Category, LabeledError, PipelineData, Signature, Type, Value, record};
+
+use crate::SkimPlugin;
+use crate::cli_arguments::default_options_diagnostics;
+use crate::nu_defaults::nu_defaults_diagnostics;
+
+/// Shows how `SKIM_DEFAULT_OPTIONS` and `SKIM_NU_DEFAULTS` were used - since invalid options in the
+/// former and unknown keys in the latter are ignored rather than failing every `sk` call.
+pub struct SkDefaults;
+
+impl PluginCommand for SkDefaults {
+    type Plugin = SkimPlugin;
+
+    fn name(&self) -> &str {
+        "sk defaults"
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build(self.name())
+            .input_output_type(Type::Nothing, Type::table())
+            .switch("ignored", "Only list the options that were not used", None)
+            .category(Category::Env)
+    }
+
+    fn description(&self) -> &str {
+        "List the options of SKIM_DEFAULT_OPTIONS and SKIM_NU_DEFAULTS, and whether they were used"
+    }
+
+    fn run(
+        &self,
+        _plugin: &SkimPlugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        _input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let span = call.head;
+        let only_ignored = call.has_flag("ignored")?;
+        let diagnostics = default_options_diagnostics(engine)
+            .into_iter()
+            .map(|diagnostic| ("SKIM_DEFAULT_OPTIONS", diagnostic))
+            .chain(
+                nu_defaults_diagnostics(engine)?
+                    .into_iter()
+                    .map(|diagnostic| ("SKIM_NU_DEFAULTS", diagnostic)),
+            );
+        let rows = diagnostics
+            .filter(|(_, diagnostic)| !only_ignored || diagnostic.problem.is_some())
+            .map(|(source, diagnostic)| {
+                let optional_string = |string: Option<String>| {
+                    string.map_or_else(
+                        || Value::nothing(span),
+                        |string| Value::string(string, span),
+                    )
+                };
+                Value::record(
+                    record! {
+                        "source" => Value::string(source, span),
+                        "option" => Value::string(diagnostic.option, span),
+                        "value" => optional_string(diagnostic.value),
+                        "status" => Value::string(
+                            match (&diagnostic.problem, diagnostic.rejected) {
+                                (None, _) => "used",
+                                (Some(_), false) => "ignored",
+                                (Some(_), true) => "rejected",
+                            },
+                            span,
+                        ),
+                        "problem" => optional_string(diagnostic.problem),
+                    },
+                    span,
+                )
+            })
+            .collect();
+        Ok(PipelineData::Value(Value::list(rows, span), None))
+    }
+}
@@ -0,0 +1,288 @@
+//! Translating Nushell's colors to the `--color` syntax of skim.
+
+use nu_ansi_term::{Color, Style};
+use nu_color_config::{color_record_to_nustyle, get_color_map, lookup_ansi_color_style};
+use nu_protocol::{Config, LabeledError, Value};
+
+/// The parts of skim's UI that can be colored. Some of them are aliases of others.
+const COMPONENTS: &[&str] = &[
+    "normal",
+    "matched",
+    "hl",
+    "current",
+    "fg+",
+    "bg+",
+    "current_match",
+    "hl+",
+    "query",
+    "spinner",
+    "info",
+    "prompt",
+    "cursor",
+    "pointer",
+    "selected",
+    "marker",
+    "header",
+    "border",
+];
+
+/// Suffixes that color only one layer of a component.
+const LAYERS: &[&str] = &["fg", "bg", "u", "underline"];
+
+/// Skim's built-in themes, which the `theme` key of a `--color` record starts from.
+const THEMES: &[&str] = &[
+    "dark",
+    "default",
+    "light",
+    "molokai",
+    "16",
+    "bw",
+    "none",
+    "empty",
+    "catppuccin_mocha",
+    "catppuccin_macchiato",
+    "catppuccin_latte",
+    "catppuccin_frappe",
+];
+
+/// Which entries of `$env.config.color_config` color which parts of skim's UI.
+const COLOR_CONFIG_COMPONENTS: &[(&str, &[&str])] = &[
+    ("header", &["header"]),
+    ("separator", &["border"]),
+    ("search_result", &["matched", "current_match"]),
+    ("hints", &["info"]),
+    ("row_index", &["cursor"]),
+];
+
+/// The theme used when `--color` is not given. Parts of skim's UI that have no matching entry
+/// in the `color_config` keep skim's default colors.
+pub fn theme_from_color_config(config: &Config) -> Option<String> {
+    let color_map = get_color_map(&config.color_config);
+    let specs = COLOR_CONFIG_COMPONENTS
+        .iter()
+        .filter_map(|(key, components)| Some((color_map.get(*key)?, *components)))
+        .flat_map(|(style, components)| {
+            components
+                .iter()
+                .flat_map(|component| style_specs(component, style))
+        })
+        .collect::<Vec<_>>();
+    (!specs.is_empty()).then(|| specs.join(","))
+}
+
+/// A `--color` record, e.g. `{theme: light, matched: green_bold, current_bg: "#303030"}`.
+///
+/// The values are colors in the same formats `color_config` accepts, or ANSI 256 color numbers.
+/// Unless a `theme` is given, the record is applied on top of the theme from the `color_config`.
+#[allow(clippy::result_large_err)]
+pub fn theme_from_record(value: &Value, config: &Config) -> Result<String, LabeledError> {
+    let record = value.as_record()?;
+    let mut specs = Vec::new();
+    match record.get("theme") {
+        Some(theme) => {
+            let name = theme.as_str()?;
+            if !THEMES.contains(&name) {
+                return Err(LabeledError::new("Invalid color theme")
+                    .with_label(format!("`{name}` is not a skim theme"), theme.span())
+                    .with_help(format!("Valid themes are {}", THEMES.join(", "))));
+            }
+            specs.push(name.to_owned());
+        }
+        None => specs.extend(theme_from_color_config(config)),
+    }
+    for (key, value) in record.iter() {
+        if key == "theme" {
+            continue;
+        }
+        let (component, layer) = parse_key(key).ok_or_else(|| {
+            LabeledError::new("Invalid color key")
+                .with_label(format!("`{key}` is not a part of skim's UI"), value.span())
+                .with_help(format!(
+                    "Valid keys are fg, bg, theme, {} - optionally with one of the suffixes {}",
+                    COMPONENTS.join(", "),
+                    LAYERS
+                        .iter()
+                        .map(|layer| format!("_{layer}"))
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                ))
+        })?;
+        let style = parse_style(value)?;
+        match layer {
+            // A color for a single layer is given as a foreground color, e.g. `{current_bg: red}`
+            Some(layer) => specs.push(format!(
+                "{component}_{layer}:{}",
+                color_spec(style.foreground.or(style.background), &style)
+            )),
+            None => specs.extend(style_specs(component, &style)),
+        }
+    }
+    Ok(specs.join(","))
+}
+
+/// Splits a key to the component and the layer, also accepting skim's `-` variant of the suffix.
+fn parse_key(key: &str) -> Option<(&str, Option<&str>)> {
+    match key {
+        "fg" => return Some(("normal", None)),
+        "bg" => return Some(("normal", Some("bg"))),
+        _ => {}
+    }
+    let (component, layer) = LAYERS
+        .iter()
+        .find_map(|layer| {
+            let component = key.strip_suffix(layer)?.strip_suffix(['_', '-'])?;
+            Some((component, Some(*layer)))
+        })
+        .unwrap_or((key, None));
+    COMPONENTS
+        .contains(&component)
+        .then_some((component, layer))
+}
+
+#[allow(clippy::result_large_err)]
+fn parse_style(value: &Value) -> Result<Style, LabeledError> {
+    let invalid = |message: String| {
+        LabeledError::new("Invalid color")
+            .with_label(message, value.span())
+            .with_help(
+                "Colors are given like in `$env.config.color_config`, or as ANSI 256 color numbers",
+            )
+    };
+    match value {
+        Value::Int { val, .. } => u8::try_from(*val)
+            .map(|index| Color::Fixed(index).normal())
+            .map_err(|_| invalid(format!("{val} is not between 0 and 255"))),
+        Value::String { val, .. } => {
+            let style = lookup_ansi_color_style(val);
+            // Unknown names are silently looked up as the default color
+            if style == Style::default() || (style == Color::Default.normal() && val != "default") {
+                Err(invalid(format!("`{val}` is not a color")))
+            } else {
+                Ok(style)
+            }
+        }
+        Value::Record { .. } => Ok(color_record_to_nustyle(value)),
+        _ => Err(invalid(format!(
+            "expected a string, an int or a record, got {}",
+            value.get_type()
+        ))),
+    }
+}
+
+/// Skim colors the foreground and the background of a component separately.
+fn style_specs(component: &str, style: &Style) -> Vec<String> {
+    let mut specs = vec![format!(
+        "{component}:{}",
+        color_spec(style.foreground, style)
+    )];
+    if let Some(background) = style.background {
+        specs.push(format!(
+            "{component}_bg:{}",
+            color_spec(Some(background), &Style::default())
+        ));
+    }
+    specs
+}
+
+/// The color, followed by the attributes of the style.
+fn color_spec(color: Option<Color>, style: &Style) -> String {
+    let mut spec = match color {
+        None => String::new(),
+        Some(Color::Default) => "-1".to_owned(),
+        Some(Color::Rgb(r, g, b)) => format!("#{r:02x}{g:02x}{b:02x}"),
+        Some(Color::Fixed(index)) => index.to_string(),
+        Some(color) => ansi_index(color).to_string(),
+    };
+    for (is_set, modifier) in [
+        (style.is_bold, "b"),
+        (style.is_dimmed, "d"),
+        (style.is_italic, "i"),
+        (style.is_underline, "u"),
+        (style.is_reverse, "r"),
+        (style.is_strikethrough, "c"),
+    ] {
+        if is_set {
+            spec.push(':');
+            spec.push_str(modifier);
+        }
+    }
+    spec
+}
+
+fn ansi_index(color: Color) -> u8 {
+    match color {
+        Color::Black => 0,
+        Color::Red => 1,
+        Color::Green => 2,
+        Color::Yellow => 3,
+        Color::Blue => 4,
+        Color::Purple | Color::Magenta => 5,
+        Color::Cyan => 6,
+        Color::White => 7,
+        Color::DarkGray => 8,
+        Color::LightRed => 9,
+        Color::LightGreen => 10,
+        Color::LightYellow => 11,
+        Color::LightBlue => 12,
+        Color::LightPurple | Color::LightMagenta => 13,
+        Color::LightCyan => 14,
+        Color::LightGray => 15,
+        Color::Fixed(index) => index,
+        Color::Rgb(..) | Color::Default => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nu_protocol::record;
+
+    use super::*;
+
+    #[test]
+    fn color_config_is_translated() {
+        let mut config = Config::default();
+        config.color_config.clear();
+        config
+            .color_config
+            .insert("header".to_owned(), Value::test_string("green_bold"));
+        config.color_config.insert(
+            "search_result".to_owned(),
+            Value::test_record(record! {
+                "fg" => Value::test_string("white"),
+                "bg" => Value::test_string("#ff0000"),
+            }),
+        );
+        assert_eq!(
+            theme_from_color_config(&config).as_deref(),
+            Some(
+                "header:2:b,matched:7,matched_bg:#ff0000,current_match:7,current_match_bg:#ff0000"
+            )
+        );
+    }
+
+    #[test]
+    fn color_records_are_validated() {
+        let config = Config::default();
+        let theme = theme_from_record(
+            &Value::test_record(record! {
+                "theme" => Value::test_string("light"),
+                "fg" => Value::test_int(250),
+                "current_bg" => Value::test_string("#303030"),
+                "hl+" => Value::test_string("red_underline"),
+            }),
+            &config,
+        )
+        .unwrap();
+        assert_eq!(theme, "light,normal:250,current_bg:#303030,hl+:1:u");
+
+        for invalid in [
+            record! { "nonsense" => Value::test_string("red") },
+            record! { "matched_nonsense" => Value::test_string("red") },
+            record! { "matched" => Value::test_string("nonsense") },
+            record! { "matched" => Value::test_int(256) },
+            record! { "theme" => Value::test_string("nonsense") },
+        ] {
+            assert!(theme_from_record(&Value::test_record(invalid), &config).is_err());
+        }
+    }
+}
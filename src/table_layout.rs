@@ -0,0 +1,240 @@
+use std::collections::HashMap;
+
+use nu_color_config::{Alignment, TextStyle, get_color_map};
+use nu_protocol::{Config, Record, Value};
+use ratatui::text::Span;
+
+use crate::nu_item::TextField;
+
+/// How many items `--table` looks at when deciding the widths of the columns.
+pub const SAMPLE_SIZE: usize = 100;
+
+const MAX_COLUMN_WIDTH: usize = 40;
+const COLUMN_SEPARATOR: &str = "  ";
+
+pub struct TableLayout {
+    /// Column names and their widths, in the order they first appeared in the sample.
+    columns: Vec<(String, usize)>,
+    styles: HashMap<String, TextStyle>,
+}
+
+impl TableLayout {
+    /// Returns `None` if there are no records in the sample.
+    pub fn from_sample<'a>(
+        sample: impl IntoIterator<Item = &'a Value>,
+        config: &Config,
+    ) -> Option<Self> {
+        let mut columns: Vec<(String, usize)> = Vec::new();
+        for value in sample {
+            let Value::Record { val, .. } = value else {
+                continue;
+            };
+            for (column, cell) in val.iter() {
+                let width = text_width(&cell_text(cell, config));
+                if let Some((_, column_width)) = columns.iter_mut().find(|(name, _)| name == column)
+                {
+                    *column_width = (*column_width).max(width);
+                } else {
+                    columns.push((column.clone(), width.max(text_width(column))));
+                }
+            }
+        }
+        if columns.is_empty() {
+            return None;
+        }
+        for (_, width) in columns.iter_mut() {
+            *width = (*width).min(MAX_COLUMN_WIDTH);
+        }
+        let styles = get_color_map(&config.color_config)
+            .into_iter()
+            .map(|(key, style)| (key, TextStyle::with_style(Alignment::Left, style)))
+            .collect();
+        Some(Self { columns, styles })
+    }
+
+    /// The column names, aligned like the rows and styled with the `header` color.
+    pub fn header(&self) -> String {
+        let style = self.styles.get("header");
+        let mut header = String::new();
+        for (index, (name, width)) in self.columns.iter().enumerate() {
+            if 0 < index {
+                header.push_str(COLUMN_SEPARATOR);
+            }
+            let (name, _) = truncate(name, *width);
+            let padding = " ".repeat(width - text_width(&name));
+            header.push_str(&paint(style, name));
+            header.push_str(&padding);
+        }
+        header
+    }
+
+    /// Lay out a record as a row of aligned cells.
+    ///
+    /// Returns the row (with ANSI colors) and the full text of the cells by their column - which
+    /// is what gets matched, since the row itself may have truncated some of it.
+    pub fn format_row(&self, record: &Record, config: &Config) -> (String, Vec<(&str, TextField)>) {
+        let mut row = String::new();
+        let mut row_chars = 0;
+        let mut fields = Vec::with_capacity(self.columns.len());
+        for (index, (column, width)) in self.columns.iter().enumerate() {
+            if 0 < index {
+                row.push_str(COLUMN_SEPARATOR);
+                row_chars += COLUMN_SEPARATOR.chars().count();
+            }
+            let Some(cell) = record.get(column) else {
+                row.push_str(&" ".repeat(*width));
+                row_chars += width;
+                continue;
+            };
+            let text = cell_text(cell, config);
+            let (shown, displayed_chars) = truncate(&text, *width);
+            let padding = " ".repeat(width - text_width(&shown));
+            let style = self.styles.get(&cell.get_type().get_non_specified_string());
+            let right_aligned = matches!(
+                cell,
+                Value::Int { .. }
+                    | Value::Float { .. }
+                    | Value::Filesize { .. }
+                    | Value::Duration { .. }
+            );
+            if right_aligned {
+                row.push_str(&padding);
+                row_chars += padding.len();
+            }
+            fields.push((
+                column.as_str(),
+                TextField {
+                    text,
+                    display_start: Some(row_chars),
+                    displayed_chars,
+                },
+            ));
+            row_chars += displayed_chars;
+            row.push_str(&paint(style, shown));
+            if !right_aligned {
+                row.push_str(&padding);
+                row_chars += padding.len();
+            }
+        }
+        (row, fields)
+    }
+}
+
+fn cell_text(cell: &Value, config: &Config) -> String {
+    cell.to_expanded_string(", ", config)
+        .replace(['\n', '\r'], " ")
+}
+
+fn text_width(text: &str) -> usize {
+    Span::raw(text).width()
+}
+
+/// Cut the text to fit the width, marking the cut with an ellipsis. Returns the shown text, and
+/// how many characters of the original text it contains.
+fn truncate(text: &str, width: usize) -> (String, usize) {
+    if text_width(text) <= width {
+        return (text.to_owned(), text.chars().count());
+    }
+    let mut shown = String::new();
+    let mut kept_chars = 0;
+    for ch in text.chars() {
+        shown.push(ch);
+        if width <= text_width(&shown) {
+            shown.pop();
+            break;
+        }
+        kept_chars += 1;
+    }
+    shown.push('…');
+    (shown, kept_chars)
+}
+
+fn paint(style: Option<&TextStyle>, text: String) -> String {
+    match style.and_then(|style| style.color_style) {
+        Some(color_style) => color_style.paint(text).to_string(),
+        None => text,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nu_protocol::record;
+
+    use super::*;
+
+    fn layout(sample: &[Value]) -> TableLayout {
+        let mut layout = TableLayout::from_sample(sample, &Config::default()).unwrap();
+        // Colors would only get in the way of comparing the text
+        layout.styles.clear();
+        layout
+    }
+
+    #[test]
+    fn columns_are_aligned_by_the_widest_cell() {
+        let sample = [
+            Value::test_record(record! {
+                "name" => Value::test_string("foo"),
+                "size" => Value::test_int(1),
+            }),
+            Value::test_record(record! {
+                "name" => Value::test_string("a-longer-name"),
+                "size" => Value::test_int(1024),
+            }),
+        ];
+        let layout = layout(&sample);
+        assert_eq!(layout.header(), "name           size");
+
+        let Value::Record { val, .. } = &sample[0] else {
+            unreachable!()
+        };
+        let (row, fields) = layout.format_row(val, &Config::default());
+        // Numbers are aligned to the right, like in Nushell's tables
+        assert_eq!(row, "foo               1");
+        assert_eq!(
+            fields
+                .iter()
+                .map(|(column, field)| (
+                    *column,
+                    field.text.as_str(),
+                    field.display_start,
+                    field.displayed_chars
+                ))
+                .collect::<Vec<_>>(),
+            [("name", "foo", Some(0), 3), ("size", "1", Some(18), 1)],
+        );
+    }
+
+    #[test]
+    fn long_cells_are_truncated_but_fully_matched() {
+        let long = "x".repeat(MAX_COLUMN_WIDTH + 10);
+        let sample = [Value::test_record(
+            record! { "text" => Value::test_string(long.clone()) },
+        )];
+        let layout = layout(&sample);
+        let Value::Record { val, .. } = &sample[0] else {
+            unreachable!()
+        };
+        let (row, fields) = layout.format_row(val, &Config::default());
+        assert_eq!(text_width(&row), MAX_COLUMN_WIDTH);
+        assert!(row.ends_with('…'));
+        assert_eq!(fields[0].1.text, long);
+        assert_eq!(fields[0].1.displayed_chars, MAX_COLUMN_WIDTH - 1);
+    }
+
+    #[test]
+    fn missing_cells_are_left_blank() {
+        let sample = [
+            Value::test_record(record! { "a" => Value::test_string("1") }),
+            Value::test_record(record! { "b" => Value::test_string("2") }),
+        ];
+        let layout = layout(&sample);
+        let Value::Record { val, .. } = &sample[1] else {
+            unreachable!()
+        };
+        let (row, fields) = layout.format_row(val, &Config::default());
+        assert_eq!(row, "   2");
+        assert_eq!(fields.len(), 1);
+        assert_eq!(fields[0].0, "b");
+        assert_eq!(fields[0].1.display_start, Some(3));
+    }
+}